@@ -0,0 +1,332 @@
+//! Generates typed bindings for the functions and mappings of `program/mori.aleo`.
+//!
+//! Only the declarations are parsed: structs and records, mapping key and value
+//! types, and function inputs and outputs. Instructions are skipped.
+
+use std::{env, fmt::Write, fs, path::Path};
+
+const PROGRAM: &str = "program/mori.aleo";
+
+#[derive(Debug)]
+struct Param {
+    name: String,
+    ty: String,
+    record: bool,
+}
+
+#[derive(Debug, Default)]
+struct Function {
+    name: String,
+    inputs: Vec<Param>,
+    outputs: Vec<Param>,
+}
+
+#[derive(Debug)]
+struct Mapping {
+    name: String,
+    key: String,
+    value: String,
+}
+
+#[derive(Debug)]
+struct Struct {
+    name: String,
+    fields: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Program {
+    id: String,
+    structs: Vec<Struct>,
+    mappings: Vec<Mapping>,
+    functions: Vec<Function>,
+}
+
+enum Block {
+    None,
+    Struct,
+    Mapping,
+    Function,
+    Skip,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={PROGRAM}");
+    let source = fs::read_to_string(PROGRAM).unwrap_or_else(|e| panic!("read {PROGRAM}: {e}"));
+    let program = parse(&source);
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("mori_program.rs");
+    fs::write(out, generate(&program)).unwrap();
+}
+
+fn parse(source: &str) -> Program {
+    let mut program = Program::default();
+    let mut block = Block::None;
+
+    for (no, line) in source.lines().enumerate() {
+        let (code, comment) = match line.split_once("//") {
+            Some((code, comment)) => (code.trim(), comment.trim()),
+            None => (line.trim(), ""),
+        };
+        if code.is_empty() {
+            continue;
+        }
+        let words = code
+            .trim_end_matches([';', ':'])
+            .split_whitespace()
+            .collect::<Vec<_>>();
+        let fail = |msg: &str| -> ! { panic!("{PROGRAM}:{}: {msg}: {line}", no + 1) };
+
+        if !line.starts_with(char::is_whitespace) {
+            block = match words.as_slice() {
+                ["program", id] => {
+                    program.id = id.to_string();
+                    Block::None
+                }
+                ["import", _] => Block::None,
+                ["struct" | "record", name] => {
+                    program.structs.push(Struct {
+                        name: name.to_string(),
+                        fields: vec![],
+                    });
+                    Block::Struct
+                }
+                ["mapping", name] => {
+                    program.mappings.push(Mapping {
+                        name: name.to_string(),
+                        key: String::new(),
+                        value: String::new(),
+                    });
+                    Block::Mapping
+                }
+                ["function", name] => {
+                    program.functions.push(Function {
+                        name: name.to_string(),
+                        ..Default::default()
+                    });
+                    Block::Function
+                }
+                ["finalize" | "closure", _] => Block::Skip,
+                _ => fail("unexpected declaration"),
+            };
+            continue;
+        }
+
+        match block {
+            Block::Struct => match words.as_slice() {
+                [field, "as", _] => {
+                    let s = program.structs.last_mut().unwrap();
+                    s.fields.push(field.to_string());
+                }
+                _ => fail("invalid struct member"),
+            },
+            Block::Mapping => {
+                let m = program.mappings.last_mut().unwrap();
+                // `key as T.public` or the older `key left as T.public`
+                match words.as_slice() {
+                    ["key", .., "as", ty] => m.key = plain_type(ty).to_string(),
+                    ["value", .., "as", ty] => m.value = plain_type(ty).to_string(),
+                    _ => fail("invalid mapping member"),
+                }
+            }
+            Block::Function => {
+                let f = program.functions.last_mut().unwrap();
+                let (params, register, ty) = match words.as_slice() {
+                    ["input", register, "as", ty] => (&mut f.inputs, register, ty),
+                    ["output", register, "as", ty] => (&mut f.outputs, register, ty),
+                    _ => continue,
+                };
+                let name = if comment.is_empty() {
+                    register.to_string()
+                } else {
+                    comment.to_string()
+                };
+                params.push(Param {
+                    name,
+                    ty: plain_type(ty).to_string(),
+                    record: ty.ends_with(".record"),
+                });
+            }
+            Block::None => fail("statement outside of a declaration"),
+            Block::Skip => {}
+        }
+    }
+
+    if program.id.is_empty() {
+        panic!("{PROGRAM}: missing program declaration");
+    }
+    program
+}
+
+/// `u128.public` -> `u128`, `Vote.record` -> `Vote`.
+fn plain_type(ty: &str) -> &str {
+    ty.split('.').next().unwrap()
+}
+
+fn rust_type(ty: &str) -> String {
+    match ty {
+        "u8" | "u16" | "u32" | "u64" | "u128" | "i8" | "i16" | "i32" | "i64" | "i128" => {
+            ty.to_string()
+        }
+        "boolean" => "bool".to_string(),
+        "address" => "crate::utils::AleoAddress".to_string(),
+        // program structs and records are mirrored in cores.rs
+        s if s.starts_with(char::is_uppercase) => format!("crate::cores::{s}"),
+        s => panic!("{PROGRAM}: type {s} has no binding"),
+    }
+}
+
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .map(|w| {
+            let mut c = w.chars();
+            c.next()
+                .map(|f| f.to_ascii_uppercase().to_string() + c.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn generate(program: &Program) -> String {
+    let mut out = String::new();
+    let w = &mut out;
+    writeln!(w, "// Generated by build.rs from {PROGRAM}, do not edit.\n").unwrap();
+    writeln!(w, "pub const PROGRAM_ID: &str = {:?};\n", program.id).unwrap();
+
+    // functions
+    writeln!(
+        w,
+        "#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]"
+    )
+    .unwrap();
+    writeln!(w, "#[serde(rename_all = \"snake_case\")]").unwrap();
+    writeln!(w, "pub enum Function {{").unwrap();
+    for f in &program.functions {
+        writeln!(w, "    {},", pascal_case(&f.name)).unwrap();
+    }
+    writeln!(w, "}}\n").unwrap();
+
+    writeln!(w, "impl Function {{").unwrap();
+    writeln!(
+        w,
+        "    pub const ALL: [Function; {}] = [{}];\n",
+        program.functions.len(),
+        program
+            .functions
+            .iter()
+            .map(|f| format!("Function::{}", pascal_case(&f.name)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+    .unwrap();
+    writeln!(w, "    pub fn name(&self) -> &'static str {{").unwrap();
+    writeln!(w, "        match self {{").unwrap();
+    for f in &program.functions {
+        writeln!(
+            w,
+            "            Function::{} => {:?},",
+            pascal_case(&f.name),
+            f.name
+        )
+        .unwrap();
+    }
+    writeln!(w, "        }}\n    }}\n}}\n").unwrap();
+
+    writeln!(w, "impl std::str::FromStr for Function {{").unwrap();
+    writeln!(w, "    type Err = MoriError;\n").unwrap();
+    writeln!(w, "    fn from_str(s: &str) -> Result<Self> {{").unwrap();
+    writeln!(w, "        match s {{").unwrap();
+    for f in &program.functions {
+        writeln!(
+            w,
+            "            {:?} => Ok(Function::{}),",
+            f.name,
+            pascal_case(&f.name)
+        )
+        .unwrap();
+    }
+    writeln!(
+        w,
+        "            _ => Err(MoriError::decode(format!(\"unknown function {{s}}\"))),"
+    )
+    .unwrap();
+    writeln!(w, "        }}\n    }}\n}}\n").unwrap();
+
+    // mappings
+    for m in &program.mappings {
+        writeln!(w, "/// The `{}` mapping.", m.name).unwrap();
+        writeln!(w, "pub mod {} {{", m.name).unwrap();
+        writeln!(w, "    pub const NAME: &str = {:?};", m.name).unwrap();
+        writeln!(w, "    pub type Key = {};", rust_type(&m.key)).unwrap();
+        writeln!(w, "    pub type Value = {};", rust_type(&m.value)).unwrap();
+        writeln!(w, "}}\n").unwrap();
+    }
+
+    // function inputs and outputs
+    for f in &program.functions {
+        let function = pascal_case(&f.name);
+        for (suffix, params) in [("Inputs", &f.inputs), ("Outputs", &f.outputs)] {
+            let name = format!("{function}{suffix}");
+            writeln!(w, "/// {suffix} of `{}`.", f.name).unwrap();
+            writeln!(w, "#[derive(Debug, Clone, PartialEq)]").unwrap();
+            writeln!(w, "pub struct {name} {{").unwrap();
+            for p in params {
+                writeln!(w, "    pub {}: {},", p.name, rust_type(&p.ty)).unwrap();
+            }
+            writeln!(w, "}}\n").unwrap();
+
+            writeln!(w, "impl {name} {{").unwrap();
+            writeln!(w, "    pub const FUNCTION: Function = Function::{function};").unwrap();
+            writeln!(w, "    pub const LEN: usize = {};\n", params.len()).unwrap();
+
+            if suffix == "Inputs" && params.iter().all(|p| !p.record) {
+                writeln!(w, "    pub fn to_inputs(&self) -> Vec<String> {{").unwrap();
+                writeln!(w, "        vec![").unwrap();
+                for p in params {
+                    writeln!(w, "            ToAleoInput::to_input(&self.{}),", p.name).unwrap();
+                }
+                writeln!(w, "        ]\n    }}\n").unwrap();
+            }
+
+            writeln!(
+                w,
+                "    pub fn from_values<N: Network>(values: &[Value<N>]) -> Result<Self> {{"
+            )
+            .unwrap();
+            writeln!(
+                w,
+                "        check_len({:?}, {:?}, Self::LEN, values.len())?;",
+                f.name,
+                suffix.to_lowercase()
+            )
+            .unwrap();
+            writeln!(w, "        Ok(Self {{").unwrap();
+            for (i, p) in params.iter().enumerate() {
+                let decode = if p.record {
+                    format!("FromAleoRecord::from_record(record_value(&values[{i}])?)?")
+                } else {
+                    format!("FromAleoPlaintext::from_plaintext(plaintext_value(&values[{i}])?)?")
+                };
+                writeln!(w, "            {}: {decode},", p.name).unwrap();
+            }
+            writeln!(w, "        }})\n    }}\n}}\n").unwrap();
+        }
+    }
+
+    // fails to compile when a mirrored struct loses a field of the program struct
+    for s in &program.structs {
+        writeln!(w, "#[allow(dead_code)]").unwrap();
+        writeln!(
+            w,
+            "fn check_{}(value: &{}) {{",
+            s.name.to_lowercase(),
+            rust_type(&s.name)
+        )
+        .unwrap();
+        for field in s.fields.iter().filter(|f| *f != "owner") {
+            writeln!(w, "    let _ = &value.{field};").unwrap();
+        }
+        writeln!(w, "}}\n").unwrap();
+    }
+
+    out
+}
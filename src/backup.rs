@@ -0,0 +1,350 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use aleo_rust::Network;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    db::{DBMap, Database},
+    error::{MoriError, Result},
+    keys::KeyCodec,
+    schema::{Migrate, Versioned},
+    Mori,
+};
+
+/// Name prefix of the checkpoint directories, followed by the creation time in
+/// milliseconds.
+const BACKUP_PREFIX: &str = "mori-";
+
+#[derive(Debug, Clone)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    /// Seconds between two scheduled backups, 0 disables them.
+    pub interval_secs: u64,
+    /// Number of backups kept in `dir`, older ones are removed.
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub created_at: u64, // unix millis
+}
+
+/// Checkpoints the database into a new directory of `dir` and removes the
+/// backups beyond the newest `keep`.
+///
+/// A checkpoint hard-links the live SST files, so it is cheap when `dir` is on
+/// the same filesystem as the database and a full copy otherwise.
+pub fn create_backup(db: &Database, dir: &Path, keep: usize) -> Result<BackupInfo> {
+    fs::create_dir_all(dir)?;
+    let mut created_at = now_millis();
+    // two backups within the same millisecond would share a name
+    if let Some(last) = list_backups(dir)?.last() {
+        created_at = created_at.max(last.created_at + 1);
+    }
+    let path = dir.join(format!("{BACKUP_PREFIX}{created_at}"));
+    db.checkpoint(&path)?;
+
+    let removed = prune_backups(dir, keep.max(1))?;
+    if !removed.is_empty() {
+        tracing::info!(
+            "removed {} old backups from {}",
+            removed.len(),
+            dir.display()
+        );
+    }
+    Ok(BackupInfo { path, created_at })
+}
+
+/// The backups of `dir`, oldest first.
+pub fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut backups = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let created_at = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(BACKUP_PREFIX))
+            .and_then(|millis| millis.parse().ok());
+        if let Some(created_at) = created_at {
+            backups.push(BackupInfo { path, created_at });
+        }
+    }
+    backups.sort_by_key(|b| b.created_at);
+    Ok(backups)
+}
+
+/// Removes the backups of `dir` beyond the newest `keep`, returns the removed ones.
+pub fn prune_backups(dir: &Path, keep: usize) -> Result<Vec<BackupInfo>> {
+    let mut backups = list_backups(dir)?;
+    let stale = backups.len().saturating_sub(keep);
+    let removed = backups.drain(..stale).collect::<Vec<_>>();
+    for backup in &removed {
+        fs::remove_dir_all(&backup.path)?;
+    }
+    Ok(removed)
+}
+
+/// Replaces the database at `db_path` by the backup at `backup`.
+///
+/// Must run while no process has the database open. The replaced database is
+/// moved next to it rather than deleted, its new path is returned.
+pub fn restore_backup(backup: &Path, db_path: &Path) -> Result<Option<PathBuf>> {
+    if !backup.join("CURRENT").is_file() {
+        return Err(MoriError::validation(format!(
+            "{} is not a database backup",
+            backup.display()
+        )));
+    }
+
+    let replaced = match db_path.exists() {
+        true => {
+            let mut aside = db_path.as_os_str().to_owned();
+            aside.push(format!(".replaced-{}", now_millis()));
+            let aside = PathBuf::from(aside);
+            fs::rename(db_path, &aside)?;
+            Some(aside)
+        }
+        false => None,
+    };
+    copy_dir(backup, db_path)?;
+    Ok(replaced)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        match entry.file_type()?.is_dir() {
+            true => copy_dir(&entry.path(), &target)?,
+            false => {
+                fs::copy(entry.path(), target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// One entry of a JSON-lines dump, `{"table": .., "key": .., "value": ..}`.
+#[derive(Debug, Serialize)]
+struct DumpLine<'a, K, V> {
+    table: &'a str,
+    key: &'a K,
+    value: &'a V,
+}
+
+#[derive(Debug, Deserialize)]
+struct DumpTable {
+    table: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DumpEntry<K, V> {
+    key: K,
+    value: V,
+}
+
+/// A table that can be written to and read back from a JSON-lines dump.
+///
+/// Entries are dumped decoded, so a dump does not depend on the key layout or
+/// on the value versions of the database it was taken from. Indexes are not
+/// dumped, they are rebuilt as the entries are imported.
+pub trait Dump: Migrate {
+    /// Writes one line per entry, returns the number of entries.
+    fn export_lines(&self, out: &mut dyn Write) -> Result<usize>;
+
+    /// Inserts the entry of a line naming this table.
+    fn import_line(&self, line: &str) -> Result<()>;
+}
+
+impl<K, V> Dump for DBMap<K, V>
+where
+    K: KeyCodec + Serialize + DeserializeOwned,
+    V: Versioned,
+{
+    fn export_lines(&self, out: &mut dyn Write) -> Result<usize> {
+        let table = self.table();
+        let mut count = 0;
+        for entry in self.iter() {
+            let (key, value) = entry?;
+            let line = DumpLine {
+                table: &table,
+                key: &key,
+                value: &value,
+            };
+            // written as text, `serde_json::Value` cannot hold the u128 ids
+            serde_json::to_writer(&mut *out, &line).map_err(MoriError::decode)?;
+            out.write_all(b"\n")?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn import_line(&self, line: &str) -> Result<()> {
+        let entry: DumpEntry<K, V> = serde_json::from_str(line).map_err(MoriError::decode)?;
+        self.insert(&entry.key, &entry.value)
+    }
+}
+
+/// Writes every entry of `tables` to `out`, returns the number of entries.
+pub fn export_tables(tables: &[&dyn Dump], out: &mut dyn Write) -> Result<usize> {
+    let mut count = 0;
+    for table in tables {
+        count += table.export_lines(out)?;
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+/// Inserts the entries of a dump into `tables`, returns the number of entries.
+pub fn import_tables(tables: &[&dyn Dump], input: impl BufRead) -> Result<usize> {
+    let mut count = 0;
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let number = number + 1;
+        let DumpTable { table: name } = serde_json::from_str(&line)
+            .map_err(|e| MoriError::decode(format!("line {number}: {e}")))?;
+        let table = tables
+            .iter()
+            .find(|t| t.table() == name)
+            .ok_or_else(|| MoriError::validation(format!("line {number}: unknown table {name}")))?;
+        table
+            .import_line(&line)
+            .map_err(|e| MoriError::decode(format!("line {number}: {e}")))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+impl<N: Network> Mori<N> {
+    fn dump_tables(&self) -> Vec<&dyn Dump> {
+        vec![
+            &self.network_height as &dyn Dump,
+            &self.mori_nodes,
+            &self.votes,
+            &self.skipped_votes,
+            &self.players,
+            &self.finished_games,
+            &self.positions,
+            &self.rejections,
+            &self.chain_nodes,
+            &self.node_births,
+            &self.majority_credits,
+            &self.scheduled_opens,
+            &self.timed_out_turns,
+        ]
+    }
+
+    /// Takes a backup now, see `create_backup`.
+    pub fn backup(&self, config: &BackupConfig) -> Result<BackupInfo> {
+        create_backup(&self.db, &config.dir, config.keep)
+    }
+
+    /// Takes a backup every `config.interval_secs` on a background thread.
+    pub fn schedule_backups(&self, config: BackupConfig) {
+        if config.interval_secs == 0 {
+            return;
+        }
+        if !self.db.can_checkpoint() {
+            tracing::warn!("the storage has no checkpoints, scheduled backups are off");
+            return;
+        }
+        let self_clone = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(config.interval_secs));
+            match self_clone.backup(&config) {
+                Ok(backup) => {
+                    tracing::info!("backed up the database to {}", backup.path.display())
+                }
+                Err(e) => tracing::error!("backup error: {:?}", e),
+            }
+        });
+    }
+
+    /// Dumps every table to a JSON-lines file, returns the number of entries.
+    pub fn export_jsonl(&self, path: &Path) -> Result<usize> {
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        export_tables(&self.dump_tables(), &mut out)
+    }
+
+    /// Loads a dump written by `export_jsonl`, returns the number of entries.
+    pub fn import_jsonl(&self, path: &Path) -> Result<usize> {
+        let input = BufReader::new(fs::File::open(path)?);
+        import_tables(&self.dump_tables(), input)
+    }
+}
+
+#[test]
+fn test_backup_retention() {
+    let db = Database::test_open().unwrap();
+    let map: DBMap<u128, String> = db.map("backup_entries");
+    map.insert(&1, &"one".to_string()).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("mori_backups_{}", now_millis()));
+    let first = create_backup(&db, &dir, 2).unwrap();
+    let second = create_backup(&db, &dir, 2).unwrap();
+    let third = create_backup(&db, &dir, 2).unwrap();
+    assert!(second.created_at > first.created_at);
+    assert_eq!(list_backups(&dir).unwrap(), vec![second, third.clone()]);
+
+    let restored = dir.join("restored");
+    assert_eq!(restore_backup(&third.path, &restored).unwrap(), None);
+    let restored_db = rocksdb::DB::open_default(&restored).unwrap();
+    let key = [b"backup_entries".as_slice(), &1u128.to_key_bytes()].concat();
+    assert!(restored_db.get(key).unwrap().is_some());
+    drop(restored_db);
+
+    let replaced = restore_backup(&third.path, &restored).unwrap().unwrap();
+    assert!(replaced.join("CURRENT").is_file());
+    assert!(restore_backup(&dir, &restored).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_dump_roundtrip() {
+    let db = Database::test_open().unwrap();
+    let pairs: DBMap<(u128, String), u32> = db.map("dump_pairs");
+    let names: DBMap<String, String> = db.map("dump_names");
+    pairs.insert(&(u128::MAX, "a".to_string()), &1).unwrap();
+    pairs.insert(&(2, "b".to_string()), &2).unwrap();
+    names
+        .insert(&"alice".to_string(), &"at1".to_string())
+        .unwrap();
+
+    let tables: [&dyn Dump; 2] = [&pairs, &names];
+    let mut out = vec![];
+    assert_eq!(export_tables(&tables, &mut out).unwrap(), 3);
+
+    pairs
+        .batch_remove(&vec![(u128::MAX, "a".to_string()), (2, "b".to_string())])
+        .unwrap();
+    names.remove(&"alice".to_string()).unwrap();
+    assert_eq!(import_tables(&tables, out.as_slice()).unwrap(), 3);
+    assert_eq!(pairs.get(&(u128::MAX, "a".to_string())).unwrap(), Some(1));
+    assert_eq!(
+        names.get(&"alice".to_string()).unwrap(),
+        Some("at1".to_string())
+    );
+
+    let unknown = br#"{"table":"nope","key":1,"value":1}"#;
+    assert!(import_tables(&tables, unknown.as_slice()).is_err());
+}
@@ -1,28 +1,108 @@
-use std::{collections::HashMap, str::FromStr};
+use std::collections::HashMap;
 
-use aleo_rust::{Identifier, Network, Plaintext, Record};
-use anyhow::anyhow;
+use aleo_rust::{Network, Plaintext};
 use serde::{Deserialize, Serialize};
 
-use crate::utils::{
-    entry_to_plain, handle_addr_plaintext, handle_from_plaintext, handle_i8_plaintext,
-    handle_u128_plaintext, handle_u8_plaintext,
-};
+use crate::error::Result;
+use crate::utils::{aleo_struct, AleoAddress, FromAleoPlaintext, ToAleoInput};
 
+/// Two bits per square, `0b01` black, `0b10` white and `0b00` empty.
+/// Every constructor checks that no square holds `0b11`.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(try_from = "u128", into = "u128")]
 pub struct GameState(u128);
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameStateError {
+    InvalidSquare { pos: u8, bits: u8 },
+    InvalidCell { pos: usize, value: i8 },
+    InvalidLength(usize),
+    StatusMismatch { status: i8, reason: &'static str },
+}
+
+impl std::fmt::Display for GameStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameStateError::InvalidSquare { pos, bits } => {
+                write!(f, "invalid square value {bits:#04b} at {pos}")
+            }
+            GameStateError::InvalidCell { pos, value } => {
+                write!(f, "invalid cell value {value} at {pos}")
+            }
+            GameStateError::InvalidLength(len) => write!(f, "expected 64 cells, got {len}"),
+            GameStateError::StatusMismatch { status, reason } => {
+                write!(f, "game status {status} does not match the board: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GameStateError {}
+
+impl TryFrom<u128> for GameState {
+    type Error = GameStateError;
+
+    fn try_from(value: u128) -> Result<Self, Self::Error> {
+        for pos in 0..64 {
+            let bits = ((value >> (2 * pos)) & 0b11) as u8;
+            if bits == 0b11 {
+                return Err(GameStateError::InvalidSquare { pos, bits });
+            }
+        }
+        Ok(GameState(value))
+    }
+}
+
+impl From<GameState> for u128 {
+    fn from(state: GameState) -> Self {
+        state.0
+    }
+}
+
+/// The program stores a board as a `u128`.
+impl<N: Network> FromAleoPlaintext<N> for GameState {
+    fn from_plaintext(plaintext: &Plaintext<N>) -> Result<Self> {
+        Ok(GameState::try_from(u128::from_plaintext(plaintext)?)?)
+    }
+}
+
+impl ToAleoInput for GameState {
+    fn to_input(&self) -> String {
+        self.0.to_input()
+    }
+}
+
+impl TryFrom<&[i8]> for GameState {
+    type Error = GameStateError;
+
+    fn try_from(vec: &[i8]) -> Result<Self, Self::Error> {
+        if vec.len() != 64 {
+            return Err(GameStateError::InvalidLength(vec.len()));
+        }
+        let mut result = 0;
+        for (pos, value) in vec.iter().enumerate() {
+            let square = match value {
+                0 => 0b00,
+                1 => 0b01,
+                -1 => 0b10,
+                _ => {
+                    return Err(GameStateError::InvalidCell { pos, value: *value });
+                }
+            };
+            result |= square << (2 * pos);
+        }
+        Ok(GameState(result))
+    }
+}
+
 impl GameState {
     pub fn pretty(&self) -> String {
-        // two bits per square
         let mut result = String::new();
         for i in 0..64 {
-            let square = (self.0 >> (2 * i)) & 0b11;
-            match square {
-                0b00 => result.push_str(" ."),
-                0b01 => result.push_str(" B"),
-                0b10 => result.push_str(" W"),
-                _ => panic!("Invalid square value"),
+            match self.side_at(i) {
+                None => result.push_str(" ."),
+                Some(Side::Black) => result.push_str(" B"),
+                Some(Side::White) => result.push_str(" W"),
             }
             if i % 8 == 7 {
                 result.push('\n');
@@ -35,32 +115,65 @@ impl GameState {
         self.0
     }
 
-    pub fn from_vec_i8(vec: &[i8]) -> Self {
+    pub fn from_vec_i8(vec: &[i8]) -> Result<Self, GameStateError> {
+        GameState::try_from(vec)
+    }
+
+    /// Moves the disc on every square `pos` to `f(pos)`, `f` must be a permutation of the squares.
+    pub(crate) fn permute(&self, f: impl Fn(u8) -> u8) -> GameState {
         let mut result = 0;
-        for i in 0..64 {
-            let square = match vec[i] {
-                0 => 0b00,
-                1 => 0b01,
-                -1 => 0b10,
-                _ => panic!("Invalid square value"),
-            };
-            result |= square << (2 * i);
+        for pos in 0..64 {
+            result |= ((self.0 >> (2 * pos)) & 0b11) << (2 * f(pos));
         }
         GameState(result)
     }
 
     pub fn to_vec_i8(&self) -> Vec<i8> {
-        let mut result = Vec::new();
+        (0..64)
+            .map(|i| match self.side_at(i) {
+                None => 0,
+                Some(Side::Black) => 1,
+                Some(Side::White) => -1,
+            })
+            .collect()
+    }
+
+    /// Checks that a game status agrees with the board, voters (black) are to move.
+    /// A game is over exactly when neither side has a legal move.
+    pub fn check_status(&self, game_status: i8) -> Result<(), GameStateError> {
+        let (black, white) = self.count();
+        if black + white < 4 {
+            return Err(GameStateError::StatusMismatch {
+                status: game_status,
+                reason: "fewer discs than the opening",
+            });
+        }
+        let can_move =
+            !self.legal_moves(Side::Black).is_empty() || !self.legal_moves(Side::White).is_empty();
+        match (game_status == 0, can_move) {
+            (true, false) => Err(GameStateError::StatusMismatch {
+                status: game_status,
+                reason: "no side can move in an ongoing game",
+            }),
+            (false, true) => Err(GameStateError::StatusMismatch {
+                status: game_status,
+                reason: "a finished game still has legal moves",
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of (black, white) discs on the board.
+    pub fn count(&self) -> (u8, u8) {
+        let (mut black, mut white) = (0, 0);
         for i in 0..64 {
-            let square = (self.0 >> (2 * i)) & 0b11;
-            match square {
-                0b00 => result.push(0),
-                0b01 => result.push(1),
-                0b10 => result.push(-1),
-                _ => panic!("Invalid square value"),
+            match (self.0 >> (2 * i)) & 0b11 {
+                0b01 => black += 1,
+                0b10 => white += 1,
+                _ => {}
             }
         }
-        result
+        (black, white)
     }
 
     pub fn zero() -> Self {
@@ -79,43 +192,171 @@ impl GameState {
         }
         false
     }
+
+    pub fn side_at(&self, pos: u8) -> Option<Side> {
+        match (self.0 >> (2 * pos)) & 0b11 {
+            0b01 => Some(Side::Black),
+            0b10 => Some(Side::White),
+            _ => None,
+        }
+    }
+
+    pub fn legal_moves(&self, side: Side) -> Vec<u8> {
+        (0..64)
+            .filter(|&pos| self.play(pos, side).is_some())
+            .collect()
+    }
+
+    /// Places a disc of `side` on `pos` and flips the captured discs.
+    /// `pos == 64` is a pass, which is only legal without any other move.
+    pub fn play(&self, pos: u8, side: Side) -> Option<GameState> {
+        if pos == PASS {
+            return (0..64)
+                .all(|p| self.play(p, side).is_none())
+                .then_some(*self);
+        }
+        if !self.check_pos_valid(pos) {
+            return None;
+        }
+
+        let (row, col) = ((pos / 8) as i8, (pos % 8) as i8);
+        let mut flips = vec![];
+        for (dr, dc) in DIRECTIONS {
+            let mut line = vec![];
+            let (mut r, mut c) = (row + dr, col + dc);
+            while (0..8).contains(&r) && (0..8).contains(&c) {
+                let p = (r * 8 + c) as u8;
+                match self.side_at(p) {
+                    Some(s) if s == side.opponent() => line.push(p),
+                    Some(_) => {
+                        flips.append(&mut line);
+                        break;
+                    }
+                    None => break,
+                }
+                r += dr;
+                c += dc;
+            }
+        }
+        if flips.is_empty() {
+            return None;
+        }
+
+        let mut state = self.0;
+        for p in flips.into_iter().chain(std::iter::once(pos)) {
+            state &= !(0b11 << (2 * p));
+            state |= side.bits() << (2 * p);
+        }
+        Some(GameState(state))
+    }
+}
+
+/// The square index of a pass move.
+pub const PASS: u8 = 64;
+
+const DIRECTIONS: [(i8, i8); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+/// Voters play black, the AI plays white.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Black,
+    White,
+}
+
+impl Side {
+    pub fn opponent(&self) -> Self {
+        match self {
+            Side::Black => Side::White,
+            Side::White => Side::Black,
+        }
+    }
+
+    fn bits(&self) -> u128 {
+        match self {
+            Side::Black => 0b01,
+            Side::White => 0b10,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Vote {
-    pub sender: String,
+    pub sender: AleoAddress,
     pub node_id: u128,
     pub mov: u8,
 }
 
-impl Vote {
-    pub fn try_from_record<N: Network>(record: Record<N, Plaintext<N>>) -> anyhow::Result<Self> {
-        let (sender_ident, node_id_ident, mov_ident) = (
-            Identifier::from_str("sender")?,
-            Identifier::from_str("node_id")?,
-            Identifier::from_str("mov")?,
-        );
-        const ERR: &str = "Invalid record";
-        let (sender_entry, node_id_entry, mov_entry) = (
-            record.data().get(&sender_ident).ok_or(anyhow!(ERR))?,
-            record.data().get(&node_id_ident).ok_or(anyhow!(ERR))?,
-            record.data().get(&mov_ident).ok_or(anyhow!(ERR))?,
-        );
-
-        let (sender, node_id, mov) = (
-            handle_addr_plaintext(entry_to_plain(sender_entry)?)?,
-            handle_u128_plaintext(entry_to_plain(node_id_entry)?)?,
-            handle_u8_plaintext(entry_to_plain(mov_entry)?)?,
-        );
-
-        Ok(Self {
-            sender: sender.to_string(),
-            node_id,
-            mov,
-        })
+aleo_struct!(Vote {
+    sender,
+    node_id,
+    mov
+});
+
+/// Why a vote output did not count towards a node.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteSkipReason {
+    NotOwner,
+    Undecodable,
+    UnknownNode,
+    InvalidMove,
+    GameOver,
+}
+
+impl std::fmt::Display for VoteSkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            VoteSkipReason::NotOwner => "not owner",
+            VoteSkipReason::Undecodable => "undecodable",
+            VoteSkipReason::UnknownNode => "unknown node",
+            VoteSkipReason::InvalidMove => "invalid move",
+            VoteSkipReason::GameOver => "game over",
+        };
+        write!(f, "{reason}")
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SkippedVote {
+    pub transition_id: String,
+    pub output_index: u8,
+    pub block_height: u32,
+    pub reason: VoteSkipReason,
+    pub detail: String,
+}
+
+/// A vote as it was seen on chain, kept for auditing and tallies.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VoteRecord {
+    pub vote: Vote,
+    pub transition_id: String,
+    pub block_height: u32,
+    pub block_timestamp: i64,
+    pub output_index: u8,
+    // record commitment, or the output id for public votes
+    pub commitment: String,
+}
+
+/// An execution or transition that could not be applied, kept instead of
+/// failing the whole pipeline.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Rejection {
+    pub source: String,
+    pub id: String,
+    pub reason: String,
+    pub timestamp: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct GameNode {
     pub node_id: u128,
@@ -134,13 +375,16 @@ pub struct NodeEdge {
 }
 
 impl GameNode {
-    pub fn check_and_add_vote(&mut self, vote: Vote) -> bool {
+    pub fn check_and_add_vote(&mut self, vote: Vote) -> Result<(), VoteSkipReason> {
+        if self.game_status != 0 {
+            return Err(VoteSkipReason::GameOver);
+        }
         // check mov valid
         if !self.valid_movs.contains(&vote.mov) {
-            return false;
+            return Err(VoteSkipReason::InvalidMove);
         }
         self.votes.push(vote);
-        self.game_status == 0
+        Ok(())
     }
 
     pub fn update_valid_movs(&mut self, movs: Vec<u8>) {
@@ -150,43 +394,11 @@ impl GameNode {
     pub fn is_root(&self) -> bool {
         self.from.node_id == 0
     }
-
-    pub fn from_plaintext<N: Network>(p: &Plaintext<N>) -> anyhow::Result<Self> {
-        let (node_id_ident, state_ident, from_ident, game_status_ident) = (
-            Identifier::from_str("node_id")?,
-            Identifier::from_str("state")?,
-            Identifier::from_str("from")?,
-            Identifier::from_str("game_status")?,
-        );
-        const ERR: &str = "Invalid record";
-        if let Plaintext::Struct(s, _) = p {
-            let (node_id_entry, state_entry, from_entry, game_status_entry) = (
-                s.get(&node_id_ident).ok_or(anyhow!(ERR))?,
-                s.get(&state_ident).ok_or(anyhow!(ERR))?,
-                s.get(&from_ident).ok_or(anyhow!(ERR))?,
-                s.get(&game_status_ident).ok_or(anyhow!(ERR))?,
-            );
-
-            let (node_id, state, from, game_status) = (
-                handle_u128_plaintext(node_id_entry)?,
-                handle_u128_plaintext(state_entry)?,
-                handle_from_plaintext(from_entry)?,
-                handle_i8_plaintext(game_status_entry)?,
-            );
-            Ok(Self {
-                node_id,
-                state: GameState(state),
-                from,
-                game_status,
-                valid_movs: vec![],
-                votes: vec![],
-            })
-        } else {
-            anyhow::bail!("Invalid record")
-        }
-    }
 }
 
+aleo_struct!(GameNode { node_id, state, from, game_status } off_chain { valid_movs, votes });
+aleo_struct!(NodeEdge { node_id, mov });
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RestResponse {
     #[serde(rename = "id")]
@@ -229,10 +441,15 @@ pub struct MovRequest {
 
 impl MovRequest {
     pub fn pass(node_id: u128) -> Self {
+        Self::unvoted(node_id, PASS)
+    }
+
+    /// Plays `mov` for the voters although nobody voted for it.
+    pub fn unvoted(node_id: u128, mov: u8) -> Self {
         Self {
             parent_id: node_id,
             votes: vec![Votes {
-                mov: 64,
+                mov,
                 addresses: vec![],
             }],
         }
@@ -256,7 +473,7 @@ impl MovRequest {
                     v.mov,
                     Votes {
                         mov: v.mov,
-                        addresses: vec![v.sender.clone()],
+                        addresses: vec![v.sender.to_string()],
                     },
                 );
                 continue;
@@ -264,14 +481,14 @@ impl MovRequest {
 
             match votes_map.get_mut(&v.mov) {
                 Some(votes) => {
-                    votes.addresses.push(v.sender.clone());
+                    votes.addresses.push(v.sender.to_string());
                 }
                 None => {
                     votes_map.insert(
                         v.mov,
                         Votes {
                             mov: v.mov,
-                            addresses: vec![v.sender.clone()],
+                            addresses: vec![v.sender.to_string()],
                         },
                     );
                 }
@@ -293,13 +510,90 @@ fn test_game_state_from_vec_i8() {
         1, 0, 0, 0, 0, 0,
     ];
 
-    let game_state = GameState::from_vec_i8(&game_state_vec_i8);
+    let game_state = GameState::from_vec_i8(&game_state_vec_i8).unwrap();
     let to_vec = game_state.to_vec_i8();
     assert_eq!(game_state_vec_i8, to_vec);
 
     println!("{}", game_state.pretty());
 }
 
+#[test]
+fn test_game_state_invalid() {
+    assert_eq!(
+        GameState::try_from(0b11u128 << 6),
+        Err(GameStateError::InvalidSquare { pos: 3, bits: 0b11 })
+    );
+    assert_eq!(
+        GameState::from_vec_i8(&[0; 63]),
+        Err(GameStateError::InvalidLength(63))
+    );
+    let mut cells = vec![0; 64];
+    cells[5] = 2;
+    assert_eq!(
+        GameState::from_vec_i8(&cells),
+        Err(GameStateError::InvalidCell { pos: 5, value: 2 })
+    );
+    assert!(serde_json::from_str::<GameState>(&(0b11u128 << 6).to_string()).is_err());
+
+    let zero = GameState::zero();
+    assert_eq!(zero.check_status(0), Ok(()));
+    assert!(zero.check_status(1).is_err());
+    assert!(GameState::try_from(0).unwrap().check_status(0).is_err());
+}
+
+#[test]
+fn test_game_state_count() {
+    assert_eq!(GameState::zero().count(), (2, 2));
+}
+
+#[test]
+fn test_game_state_play() {
+    let zero = GameState::zero();
+    assert_eq!(zero.legal_moves(Side::Black), vec![20, 29, 34, 43]);
+    assert!(zero.play(0, Side::Black).is_none());
+    assert!(zero.play(PASS, Side::Black).is_none());
+
+    // black f5 flips e5
+    let next = zero.play(29, Side::Black).unwrap();
+    assert_eq!(next.count(), (4, 1));
+    assert_eq!(next.side_at(28), Some(Side::Black));
+    assert_eq!(next.legal_moves(Side::White), vec![19, 21, 37]);
+}
+
+#[test]
+fn test_check_and_add_vote_reasons() {
+    let mut node = GameNode {
+        node_id: 1,
+        state: GameState::zero(),
+        from: NodeEdge { node_id: 0, mov: 0 },
+        game_status: 0,
+        valid_movs: vec![19, 26],
+        votes: vec![],
+    };
+    let vote = |mov| Vote {
+        sender: "aleo1voter".into(),
+        node_id: 1,
+        mov,
+    };
+
+    assert_eq!(node.check_and_add_vote(vote(19)), Ok(()));
+    assert_eq!(
+        node.check_and_add_vote(vote(20)),
+        Err(VoteSkipReason::InvalidMove)
+    );
+
+    node.game_status = 1;
+    assert_eq!(
+        node.check_and_add_vote(vote(26)),
+        Err(VoteSkipReason::GameOver)
+    );
+    assert_eq!(
+        node.check_and_add_vote(vote(20)),
+        Err(VoteSkipReason::GameOver)
+    );
+    assert_eq!(node.votes, vec![vote(19)]);
+}
+
 #[test]
 fn test_game_state_pretty() {
     let game_state = GameState(7083711853891053158400);
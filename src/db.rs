@@ -1,210 +1,869 @@
-use std::{env::temp_dir, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env::temp_dir,
+    fs,
+    ops::{Bound, Deref, RangeBounds},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
 
 use once_cell::sync::OnceCell;
-use serde::{de::DeserializeOwned, Serialize};
-
-const DB_PATH: &str = "./mori_db";
-
-#[derive(Clone)]
-pub struct RocksDB(Arc<rocksdb::DB>);
-
-impl RocksDB {
-    pub fn open() -> anyhow::Result<Self> {
-        static DB: OnceCell<RocksDB> = OnceCell::new();
+use serde::de::DeserializeOwned;
+
+use crate::{
+    error::{MoriError, Result},
+    keys::KeyCodec,
+    schema::{self, Migrate, Versioned},
+    store::{
+        Direction, IteratorMode, KvStore, MemoryStore, RawEntry, RocksStore, StorageKind,
+        WriteBatch,
+    },
+};
+
+pub const DB_PATH: &str = "./mori_db";
+/// Layout of the keys written by `DBMap`, see `KeyCodec`.
+pub const KEY_LAYOUT: u16 = 1;
+/// Attempts of `DBMap::update` before a conflict is returned.
+const UPDATE_RETRIES: usize = 5;
+
+/// An open store with the lock its commits take.
+///
+/// A test database also owns its temporary directory, which is removed after
+/// the store is closed.
+pub struct OpenStore {
+    store: Box<dyn KvStore>,
+    /// Held while a transaction checks its reads and writes its batch.
+    commit_lock: Mutex<()>,
+    _temp: Option<TempDir>,
+}
 
-        // Retrieve the database.
-        let database = DB
-            .get_or_try_init(|| {
-                // Customize database options.
-                let mut options = rocksdb::Options::default();
-                options.set_compression_type(rocksdb::DBCompressionType::Lz4);
-                let rocksdb = {
-                    options.increase_parallelism(2);
-                    options.create_if_missing(true);
+impl OpenStore {
+    fn new(store: impl KvStore + 'static, temp: Option<TempDir>) -> Self {
+        Self {
+            store: Box::new(store),
+            commit_lock: Mutex::new(()),
+            _temp: temp,
+        }
+    }
 
-                    Arc::new(rocksdb::DB::open(&options, DB_PATH)?)
-                };
+    fn lock_commits(&self) -> MutexGuard<'_, ()> {
+        self.commit_lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
 
-                Ok::<_, anyhow::Error>(RocksDB(rocksdb))
-            })?
-            .clone();
+impl Deref for OpenStore {
+    type Target = dyn KvStore;
 
-        Ok(database)
+    fn deref(&self) -> &Self::Target {
+        self.store.as_ref()
     }
+}
 
-    pub fn open_map<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned>(
-        prefix: &str,
-    ) -> anyhow::Result<DBMap<K, V>> {
-        let db = Self::open()?;
-
-        let prefix = prefix.as_bytes().to_vec();
+struct TempDir(PathBuf);
 
-        Ok(DBMap {
-            inner: db.inner(),
-            prefix,
-            _marker: std::marker::PhantomData,
-        })
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            tracing::warn!("failed to remove {}: {e}", self.0.display());
+        }
     }
+}
 
-    pub fn test_open() -> anyhow::Result<Self> {
-        static DB: OnceCell<RocksDB> = OnceCell::new();
+/// A handle on the store the maps live in, RocksDB or memory.
+#[derive(Clone)]
+pub struct Database(Arc<OpenStore>);
+
+impl Database {
+    /// Opens the RocksDB store at `DB_PATH`, shared by the whole process.
+    pub fn open() -> Result<Self> {
+        static DB: OnceCell<Database> = OnceCell::new();
 
         // Retrieve the database.
         let database = DB
             .get_or_try_init(|| {
-                // Customize database options.
-                let mut options = rocksdb::Options::default();
-                options.set_compression_type(rocksdb::DBCompressionType::Lz4);
-                let rocksdb = {
-                    options.increase_parallelism(2);
-                    options.create_if_missing(true);
-
-                    Arc::new(rocksdb::DB::open(&options, temp_dir())?)
-                };
-
-                Ok::<_, anyhow::Error>(RocksDB(rocksdb))
+                let store = RocksStore::open(Path::new(DB_PATH))?;
+                Ok::<_, MoriError>(Database(Arc::new(OpenStore::new(store, None))))
             })?
             .clone();
 
         Ok(database)
     }
 
-    pub fn test_open_map<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned>(
-        prefix: &str,
-    ) -> anyhow::Result<DBMap<K, V>> {
-        let db = Self::test_open()?;
+    /// Opens an empty database kept in memory, see `MemoryStore`.
+    pub fn memory() -> Self {
+        Database(Arc::new(OpenStore::new(MemoryStore::default(), None)))
+    }
 
-        let prefix = prefix.as_bytes().to_vec();
+    pub fn open_storage(kind: StorageKind) -> Result<Self> {
+        match kind {
+            StorageKind::RocksDB => Self::open(),
+            StorageKind::Memory => Ok(Self::memory()),
+        }
+    }
 
-        Ok(DBMap {
-            inner: db.inner(),
-            prefix,
+    pub fn open_map<K: KeyCodec, V: Versioned>(prefix: &str) -> Result<DBMap<K, V>> {
+        Ok(Self::open()?.map(prefix))
+    }
+
+    /// Opens a new empty database in its own temporary directory, removed
+    /// when the last map of it is dropped.
+    pub fn test_open() -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = temp_dir().join(format!("mori_test_{}_{id}", std::process::id()));
+        // left over by an earlier run that had the same pid
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        let store = RocksStore::open(&path)?;
+        Ok(Database(Arc::new(OpenStore::new(
+            store,
+            Some(TempDir(path)),
+        ))))
+    }
+
+    pub fn map<K: KeyCodec, V: Versioned>(&self, prefix: &str) -> DBMap<K, V> {
+        DBMap {
+            inner: self.inner(),
+            prefix: prefix.as_bytes().to_vec(),
+            indexes: vec![],
             _marker: std::marker::PhantomData,
-        })
+        }
     }
 
-    pub fn inner(&self) -> Arc<rocksdb::DB> {
+    pub fn inner(&self) -> Arc<OpenStore> {
         self.0.clone()
     }
+
+    /// Writes a consistent copy of the database to `path`, which must not
+    /// exist yet.
+    pub fn checkpoint(&self, path: &Path) -> Result<()> {
+        self.0.checkpoint(path)
+    }
+
+    pub fn can_checkpoint(&self) -> bool {
+        self.0.can_checkpoint()
+    }
 }
 
 #[derive(Clone)]
-pub struct DBMap<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned> {
-    pub inner: Arc<rocksdb::DB>,
+pub struct DBMap<K: KeyCodec, V: Versioned> {
+    pub inner: Arc<OpenStore>,
     prefix: Vec<u8>,
+    indexes: Vec<(Vec<u8>, Extract<K, V>)>,
     _marker: std::marker::PhantomData<(K, V)>,
 }
 
-impl<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned> DBMap<K, V> {
-    pub fn insert(&self, key: &K, value: &V) -> anyhow::Result<()> {
-        let key_bytes = bincode::serialize(key)?;
-        let value_bytes = bincode::serialize(value)?;
+/// Raw writes, `None` deletes the key.
+type RawChanges = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+/// The encoded index keys of an entry.
+type Extract<K, V> = Arc<dyn Fn(&K, &V) -> Vec<Vec<u8>> + Send + Sync>;
+
+/// A secondary index of a `DBMap<K, V>` from `IK` to the keys of its entries.
+///
+/// An index entry is the index prefix followed by the index key and the
+/// entry's key, so the entries of one index key are a prefix scan.
+pub struct Index<IK, K, V> {
+    prefix: Vec<u8>,
+    _marker: std::marker::PhantomData<fn() -> (IK, K, V)>,
+}
 
-        let real_key = [self.prefix.clone(), key_bytes].concat();
+impl<IK, K, V> Clone for Index<IK, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            prefix: self.prefix.clone(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
 
-        self.inner.put(real_key, value_bytes)?;
+impl<K: KeyCodec, V: Versioned> DBMap<K, V> {
+    /// Declares an index kept up to date by every write through this map or
+    /// a transaction. Indexes are backfilled by the startup migrations.
+    pub fn add_index<IK: KeyCodec>(
+        &mut self,
+        name: &str,
+        extract: fn(&K, &V) -> Vec<IK>,
+    ) -> Index<IK, K, V>
+    where
+        K: 'static,
+        V: 'static,
+        IK: 'static,
+    {
+        let table = String::from_utf8_lossy(&self.prefix);
+        let prefix = format!("index/{table}/{name}").into_bytes();
+        let encode: Extract<K, V> = Arc::new(move |key: &K, value: &V| {
+            extract(key, value)
+                .iter()
+                .map(KeyCodec::to_key_bytes)
+                .collect()
+        });
+        self.indexes.push((prefix.clone(), encode));
+
+        Index {
+            prefix,
+            _marker: std::marker::PhantomData,
+        }
+    }
 
-        Ok(())
+    pub fn insert(&self, key: &K, value: &V) -> Result<()> {
+        self.write(&[(key, Some(value))])
     }
 
-    pub fn batch_insert(&self, kvs: &Vec<(K, V)>) -> anyhow::Result<()> {
-        let mut batch = rocksdb::WriteBatch::default();
+    pub fn batch_insert(&self, kvs: &Vec<(K, V)>) -> Result<()> {
+        let entries = kvs.iter().map(|(k, v)| (k, Some(v))).collect::<Vec<_>>();
+        self.write(&entries)
+    }
 
-        for (key, value) in kvs {
-            let key_bytes = bincode::serialize(key)?;
-            let value_bytes = bincode::serialize(value)?;
+    pub fn remove(&self, key: &K) -> Result<()> {
+        self.write(&[(key, None)])
+    }
 
-            let real_key = [self.prefix.clone(), key_bytes].concat();
+    pub fn batch_remove(&self, keys: &Vec<K>) -> Result<()> {
+        let entries = keys.iter().map(|k| (k, None)).collect::<Vec<_>>();
+        self.write(&entries)
+    }
 
-            batch.put(real_key, value_bytes);
+    /// Keys of the entries `index` maps `index_key` to, in key order.
+    pub fn query_keys<IK: KeyCodec>(
+        &self,
+        index: &Index<IK, K, V>,
+        index_key: &IK,
+    ) -> Result<Vec<K>> {
+        let start = [index.prefix.clone(), index_key.to_key_bytes()].concat();
+        let mode = IteratorMode::From(&start, Direction::Forward);
+        let mut keys = vec![];
+        for item in self.raw_unbounded(mode) {
+            let (key, _) = item?;
+            if !key.starts_with(&start) {
+                break;
+            }
+            keys.push(K::from_key_bytes(&key[start.len()..])?);
         }
+        Ok(keys)
+    }
 
-        self.inner.write(batch)?;
+    /// Keys of the entries `index` maps into `from..to`, in index key order.
+    pub fn query_range_keys<IK: KeyCodec>(
+        &self,
+        index: &Index<IK, K, V>,
+        from: &IK,
+        to: &IK,
+    ) -> Result<Vec<K>> {
+        let start = [index.prefix.clone(), from.to_key_bytes()].concat();
+        let end = [index.prefix.clone(), to.to_key_bytes()].concat();
+        let mode = IteratorMode::From(&start, Direction::Forward);
+        let mut keys = vec![];
+        for item in self.raw_unbounded(mode) {
+            let (key, _) = item?;
+            if !key.starts_with(&index.prefix) || *key >= *end {
+                break;
+            }
+            let mut rest = &key[index.prefix.len()..];
+            IK::decode_key(&mut rest)?;
+            keys.push(K::from_key_bytes(rest)?);
+        }
+        Ok(keys)
+    }
 
-        Ok(())
+    /// Entries `index` maps into `from..to`, in index key order.
+    pub fn query_range<IK: KeyCodec>(
+        &self,
+        index: &Index<IK, K, V>,
+        from: &IK,
+        to: &IK,
+    ) -> Result<Vec<(K, V)>> {
+        let mut entries = vec![];
+        for key in self.query_range_keys(index, from, to)? {
+            if let Some(value) = self.get(&key)? {
+                entries.push((key, value));
+            }
+        }
+        Ok(entries)
     }
 
-    pub fn remove(&self, key: &K) -> anyhow::Result<()> {
-        let key_bytes = bincode::serialize(&key)?;
-        let real_key = [self.prefix.clone(), key_bytes].concat();
+    /// Entries `index` maps `index_key` to, in key order.
+    pub fn query<IK: KeyCodec>(
+        &self,
+        index: &Index<IK, K, V>,
+        index_key: &IK,
+    ) -> Result<Vec<(K, V)>> {
+        let mut entries = vec![];
+        for key in self.query_keys(index, index_key)? {
+            if let Some(value) = self.get(&key)? {
+                entries.push((key, value));
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn get_all(&self) -> Result<Vec<(K, V)>> {
+        self.iter().collect()
+    }
 
-        self.inner.delete(real_key)?;
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        let value = self.inner.get(&self.real_key(key))?;
 
-        Ok(())
+        if let Some(value) = value {
+            let value = schema::decode(&value)?;
+            Ok(Some(value))
+        } else {
+            Ok(None)
+        }
     }
 
-    pub fn batch_remove(&self, keys: &Vec<K>) -> anyhow::Result<()> {
-        let mut batch = rocksdb::WriteBatch::default();
+    /// Removes and returns the entry with the smallest key.
+    pub fn pop_front(&self) -> Result<Option<(K, V)>> {
+        let Some(entry) = self.iter().next() else {
+            return Ok(None);
+        };
+        let (key, value) = entry?;
+        self.remove(&key)?;
 
-        for key in keys {
-            let key_bytes = bincode::serialize(key)?;
-            let real_key = [self.prefix.clone(), key_bytes].concat();
+        Ok(Some((key, value)))
+    }
+
+    pub fn contain(&self, key: &K) -> Result<bool> {
+        let value = self.inner.get(&self.real_key(key))?;
+
+        Ok(value.is_some())
+    }
 
-            batch.delete(real_key);
+    /// Entries in ascending key order.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        let mode = IteratorMode::From(&self.prefix, Direction::Forward);
+        self.entries(self.raw(mode))
+    }
+
+    /// Entries in descending key order.
+    pub fn iter_rev(&self) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        let upper = prefix_end(&self.prefix);
+        let mode = match &upper {
+            Some(upper) => IteratorMode::From(upper, Direction::Reverse),
+            None => IteratorMode::End,
+        };
+        // seeking backwards lands on `upper` itself when it is a stored key
+        let raw = self
+            .raw_unbounded(mode)
+            .skip_while(move |item| {
+                matches!((item, &upper), (Ok((key, _)), Some(upper)) if **key == **upper)
+            })
+            .take_while(move |item| self.within(item, &self.prefix));
+        self.entries(raw)
+    }
+
+    /// Entries whose key is in `range`, in ascending key order.
+    pub fn range(&self, range: impl RangeBounds<K>) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        let start = match range.start_bound() {
+            Bound::Included(key) | Bound::Excluded(key) => self.real_key(key),
+            Bound::Unbounded => self.prefix.clone(),
+        };
+        let skip = match range.start_bound() {
+            Bound::Excluded(key) => Some(self.real_key(key)),
+            _ => None,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => Bound::Included(self.real_key(key)),
+            Bound::Excluded(key) => Bound::Excluded(self.real_key(key)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        let mode = IteratorMode::From(&start, Direction::Forward);
+        let raw = self
+            .raw(mode)
+            .skip_while(
+                move |item| matches!((item, &skip), (Ok((key, _)), Some(skip)) if **key == **skip),
+            )
+            .take_while(move |item| match (item, &end) {
+                (Ok((key, _)), Bound::Included(end)) => **key <= **end,
+                (Ok((key, _)), Bound::Excluded(end)) => **key < **end,
+                _ => true,
+            });
+        self.entries(raw)
+    }
+
+    /// Entries whose key starts with `prefix`, the leading components of a
+    /// composite key, in ascending key order.
+    pub fn scan_prefix<P: KeyCodec>(
+        &self,
+        prefix: &P,
+    ) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        let start = [self.prefix.clone(), prefix.to_key_bytes()].concat();
+        let mode = IteratorMode::From(&start, Direction::Forward);
+        let raw = self
+            .raw_unbounded(mode)
+            .take_while(move |item| self.within(item, &start));
+        self.entries(raw)
+    }
+
+    /// Starts a transaction on the database of this map.
+    pub fn transaction(&self) -> DBTransaction {
+        DBTransaction::new(self.inner.clone())
+    }
+
+    /// Reads, changes and writes back one value in its own transaction,
+    /// retrying when another writer changed it meanwhile.
+    pub fn update<T>(&self, key: &K, mut f: impl FnMut(&mut Option<V>) -> T) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            let mut txn = self.transaction();
+            let result = txn.update(self, key, &mut f)?;
+            match txn.commit() {
+                Ok(()) => return Ok(result),
+                Err(MoriError::Conflict(e)) if attempt < UPDATE_RETRIES => {
+                    tracing::debug!("update attempt {attempt} conflicted: {e}");
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
         }
+    }
 
+    /// Writes every entry, `None` removes it, with its index entries in one batch.
+    fn write(&self, entries: &[(&K, Option<&V>)]) -> Result<()> {
+        // reading the old index entries and replacing them must not interleave
+        let _guard = (!self.indexes.is_empty()).then(|| self.inner.lock_commits());
+
+        let mut batch = WriteBatch::default();
+        for (key, value) in entries {
+            let old = match self.indexes.is_empty() {
+                true => None,
+                false => self.get(key)?,
+            };
+            for (key, value) in self.changes(key, old.as_ref(), *value)? {
+                match value {
+                    Some(value) => batch.put(key, value),
+                    None => batch.delete(key),
+                }
+            }
+        }
         self.inner.write(batch)?;
 
         Ok(())
     }
 
-    pub fn get_all(&self) -> anyhow::Result<Vec<(K, V)>> {
-        let mut result = Vec::new();
-        let iter = self.inner.prefix_iterator(self.prefix.clone());
-        for item in iter {
+    /// The raw writes replacing `old` with `new` at `key`, index entries included.
+    fn changes(&self, key: &K, old: Option<&V>, new: Option<&V>) -> Result<RawChanges> {
+        let mut changes = vec![];
+        if let Some(old) = old {
+            changes.extend(self.index_entries(key, old).into_iter().map(|e| (e, None)));
+        }
+        if let Some(new) = new {
+            changes.extend(
+                self.index_entries(key, new)
+                    .into_iter()
+                    .map(|e| (e, Some(vec![]))),
+            );
+        }
+        changes.push((self.real_key(key), new.map(schema::encode).transpose()?));
+        Ok(changes)
+    }
+
+    fn index_entries(&self, key: &K, value: &V) -> Vec<Vec<u8>> {
+        let key_bytes = key.to_key_bytes();
+        let mut entries = vec![];
+        for (prefix, extract) in &self.indexes {
+            for index_key in extract(key, value) {
+                entries.push([prefix.as_slice(), &index_key, &key_bytes].concat());
+            }
+        }
+        entries
+    }
+
+    fn real_key(&self, key: &K) -> Vec<u8> {
+        let mut real_key = self.prefix.clone();
+        key.encode_key(&mut real_key);
+        real_key
+    }
+
+    fn decode_key(&self, real_key: &[u8]) -> Result<K> {
+        K::from_key_bytes(&real_key[self.prefix.len()..])
+    }
+
+    /// Raw entries from `mode` on, up to the first key of another table.
+    fn raw(&self, mode: IteratorMode) -> impl Iterator<Item = Result<RawEntry>> + '_ {
+        self.raw_unbounded(mode)
+            .take_while(move |item| self.within(item, &self.prefix))
+    }
+
+    fn raw_unbounded(&self, mode: IteratorMode) -> impl Iterator<Item = Result<RawEntry>> + '_ {
+        self.inner.iterator(mode)
+    }
+
+    /// Whether an entry's key starts with `prefix`, errors are passed on.
+    fn within(&self, item: &Result<RawEntry>, prefix: &[u8]) -> bool {
+        match item {
+            Ok((key, _)) => key.starts_with(prefix),
+            Err(_) => true,
+        }
+    }
+
+    fn entries<'a>(
+        &'a self,
+        raw: impl Iterator<Item = Result<RawEntry>> + 'a,
+    ) -> impl Iterator<Item = Result<(K, V)>> + 'a {
+        raw.map(move |item| {
+            let (key, value) = item?;
+            Ok((self.decode_key(&key)?, schema::decode(&value)?))
+        })
+    }
+
+    /// Writes `batch` together with `key` = `value`, in one atomic write.
+    pub(crate) fn write_with(&self, mut batch: WriteBatch, key: &K, value: &V) -> Result<()> {
+        let _guard = (!self.indexes.is_empty()).then(|| self.inner.lock_commits());
+        let old = match self.indexes.is_empty() {
+            true => None,
+            false => self.get(key)?,
+        };
+        for (key, value) in self.changes(key, old.as_ref(), Some(value))? {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        self.inner.write(batch)
+    }
+
+    /// Moves every entry to the key `f` returns for its raw key, `None` keeps it.
+    fn rekey(
+        &self,
+        batch: &mut WriteBatch,
+        f: impl Fn(&[u8]) -> Result<Option<K>>,
+    ) -> Result<usize> {
+        let mut moved = vec![];
+        for item in self.raw_unbounded(IteratorMode::From(&self.prefix, Direction::Forward)) {
             let (key, value) = item?;
-            if key.starts_with(&self.prefix) {
-                let key = &key[self.prefix.len()..];
-                let key = bincode::deserialize(key)?;
-                let value = bincode::deserialize(&value)?;
+            if !key.starts_with(&self.prefix) {
+                break;
+            }
+            if let Some(new_key) = f(&key[self.prefix.len()..])? {
+                moved.push((key, self.real_key(&new_key), value));
+            }
+        }
+
+        // old and new encodings may collide, so every delete goes first
+        for (old_key, _, _) in &moved {
+            batch.delete(old_key);
+        }
+        for (_, new_key, value) in &moved {
+            batch.put(new_key, value);
+        }
+        Ok(moved.len())
+    }
+}
+
+/// The smallest key after every key starting with `prefix`.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Writes to the maps of one database that are applied in a single batch.
+///
+/// Reads see the pending writes. Commit fails with `MoriError::Conflict` when a
+/// value read through `get` or `contain` was changed by another writer since.
+///
+/// The check and the write hold the commit lock, which other transactions and
+/// writes to indexed maps take too. A plain `DBMap` write to a map without
+/// indexes, as the `/admin/*` routes and API handlers do, can land between
+/// the two and is not detected.
+pub struct DBTransaction {
+    inner: Arc<OpenStore>,
+    reads: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+/// The pending writes of a transaction, see `DBTransaction::rollback_to`.
+pub struct Savepoint(BTreeMap<Vec<u8>, Option<Vec<u8>>>);
+
+impl DBTransaction {
+    pub fn new(inner: Arc<OpenStore>) -> Self {
+        Self {
+            inner,
+            reads: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    pub fn get<K: KeyCodec, V: Versioned>(
+        &mut self,
+        map: &DBMap<K, V>,
+        key: &K,
+    ) -> Result<Option<V>> {
+        let value = self.read(map, map.real_key(key))?;
+        value.map(|v| schema::decode(&v)).transpose()
+    }
+
+    pub fn contain<K: KeyCodec, V: Versioned>(
+        &mut self,
+        map: &DBMap<K, V>,
+        key: &K,
+    ) -> Result<bool> {
+        Ok(self.read(map, map.real_key(key))?.is_some())
+    }
+
+    /// Every entry of `map` with the pending writes applied. Scans are not
+    /// checked for conflicts on commit.
+    pub fn get_all<K: KeyCodec, V: Versioned>(&self, map: &DBMap<K, V>) -> Result<Vec<(K, V)>> {
+        debug_assert!(Arc::ptr_eq(&self.inner, &map.inner));
+        let entries = self.scan(&map.prefix)?;
+        let mut result = Vec::with_capacity(entries.len());
+        for (key, value) in entries {
+            result.push((map.decode_key(&key)?, schema::decode(&value)?));
+        }
+        Ok(result)
+    }
+
+    pub fn insert<K: KeyCodec, V: Versioned>(
+        &mut self,
+        map: &DBMap<K, V>,
+        key: &K,
+        value: &V,
+    ) -> Result<()> {
+        self.write(map, key, Some(value))
+    }
+
+    pub fn remove<K: KeyCodec, V: Versioned>(&mut self, map: &DBMap<K, V>, key: &K) -> Result<()> {
+        self.write(map, key, None)
+    }
+
+    /// Keys of the entries `index` maps `index_key` to, with the pending
+    /// writes applied.
+    pub fn query_keys<K: KeyCodec, V: Versioned, IK: KeyCodec>(
+        &self,
+        map: &DBMap<K, V>,
+        index: &Index<IK, K, V>,
+        index_key: &IK,
+    ) -> Result<Vec<K>> {
+        debug_assert!(Arc::ptr_eq(&self.inner, &map.inner));
+        let start = [index.prefix.clone(), index_key.to_key_bytes()].concat();
+        self.scan(&start)?
+            .into_keys()
+            .map(|key| K::from_key_bytes(&key[start.len()..]))
+            .collect()
+    }
 
-                result.push((key, value));
+    pub fn query<K: KeyCodec, V: Versioned, IK: KeyCodec>(
+        &mut self,
+        map: &DBMap<K, V>,
+        index: &Index<IK, K, V>,
+        index_key: &IK,
+    ) -> Result<Vec<(K, V)>> {
+        let mut entries = vec![];
+        for key in self.query_keys(map, index, index_key)? {
+            if let Some(value) = self.get(map, &key)? {
+                entries.push((key, value));
             }
         }
+        Ok(entries)
+    }
 
+    pub fn batch_remove<K: KeyCodec, V: Versioned>(
+        &mut self,
+        map: &DBMap<K, V>,
+        keys: &[K],
+    ) -> Result<()> {
+        for key in keys {
+            self.remove(map, key)?;
+        }
+        Ok(())
+    }
+
+    /// Reads, changes and writes back one value, leaving it `None` removes it.
+    pub fn update<K: KeyCodec, V: Versioned, T>(
+        &mut self,
+        map: &DBMap<K, V>,
+        key: &K,
+        f: impl FnOnce(&mut Option<V>) -> T,
+    ) -> Result<T> {
+        let mut value = self.get(map, key)?;
+        let result = f(&mut value);
+        match value {
+            Some(value) => self.insert(map, key, &value)?,
+            None => self.remove(map, key)?,
+        }
         Ok(result)
     }
 
-    pub fn get(&self, key: &K) -> anyhow::Result<Option<V>> {
-        let key_bytes = bincode::serialize(key)?;
-        let real_key = [self.prefix.clone(), key_bytes].concat();
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint(self.writes.clone())
+    }
+
+    /// Drops the writes made after `savepoint`, the reads are kept.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) {
+        self.writes = savepoint.0;
+    }
 
-        let value = self.inner.get(real_key)?;
+    pub fn commit(self) -> Result<()> {
+        let _guard = self.inner.lock_commits();
+        for (key, seen) in &self.reads {
+            if self.inner.get(key)? != *seen {
+                return Err(MoriError::Conflict(format!(
+                    "{} changed since it was read",
+                    String::from_utf8_lossy(key)
+                )));
+            }
+        }
 
-        if let Some(value) = value {
-            let value = bincode::deserialize(&value)?;
-            Ok(Some(value))
-        } else {
-            Ok(None)
+        let mut batch = WriteBatch::default();
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
         }
+        self.inner.write(batch)?;
+        Ok(())
     }
 
-    pub fn pop_front(&self) -> anyhow::Result<Option<(K, V)>> {
-        let iter = self.inner.prefix_iterator(self.prefix.clone());
+    fn write<K: KeyCodec, V: Versioned>(
+        &mut self,
+        map: &DBMap<K, V>,
+        key: &K,
+        value: Option<&V>,
+    ) -> Result<()> {
+        debug_assert!(Arc::ptr_eq(&self.inner, &map.inner));
+        let old = match map.indexes.is_empty() {
+            true => None,
+            false => self.get(map, key)?,
+        };
+        self.writes.extend(map.changes(key, old.as_ref(), value)?);
+        Ok(())
+    }
 
-        for item in iter {
+    /// Raw entries starting with `prefix`, with the pending writes applied.
+    fn scan(&self, prefix: &[u8]) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+        let mut entries = BTreeMap::new();
+        let mode = IteratorMode::From(prefix, Direction::Forward);
+        for item in self.inner.iterator(mode) {
             let (key, value) = item?;
-            if key.starts_with(&self.prefix) {
-                let key = &key[self.prefix.len()..];
-                let key = bincode::deserialize(key)?;
-                let value = bincode::deserialize(&value)?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.insert(key.to_vec(), value.to_vec());
+        }
+        let pending = self
+            .writes
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix));
+        for (key, value) in pending {
+            match value {
+                Some(value) => entries.insert(key.clone(), value.clone()),
+                None => entries.remove(key),
+            };
+        }
+        Ok(entries)
+    }
+
+    fn read<K: KeyCodec, V: Versioned>(
+        &mut self,
+        map: &DBMap<K, V>,
+        key: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        debug_assert!(Arc::ptr_eq(&self.inner, &map.inner));
+        if let Some(value) = self.writes.get(&key) {
+            return Ok(value.clone());
+        }
+        let value = self.inner.get(&key)?;
+        self.reads.entry(key).or_insert_with(|| value.clone());
+        Ok(value)
+    }
+}
 
-                self.remove(&key)?;
+impl<K: KeyCodec + DeserializeOwned, V: Versioned> Migrate for DBMap<K, V> {
+    fn table(&self) -> String {
+        String::from_utf8_lossy(&self.prefix).into_owned()
+    }
 
-                return Ok(Some((key, value)));
-            }
+    fn version(&self) -> u16 {
+        V::VERSION
+    }
+
+    fn migrate(&self, from: Option<u16>, batch: &mut WriteBatch) -> Result<usize> {
+        let start = batch.len();
+        let mode = IteratorMode::From(&self.prefix, Direction::Forward);
+        for item in self.raw(mode) {
+            let (key, value) = item?;
+            let value = match from {
+                // written before versioning, a bare version 0 payload
+                None => schema::decode_version::<V>(0, &value)?,
+                Some(_) => match schema::open_envelope(&value)? {
+                    (version, _) if version == V::VERSION => continue,
+                    (version, payload) => schema::decode_version::<V>(version, payload)?,
+                },
+            };
+            batch.put(key, schema::encode(&value)?);
         }
 
-        Ok(None)
+        Ok(batch.len() - start)
     }
 
-    pub fn contain(&self, key: &K) -> anyhow::Result<bool> {
-        let key_bytes = bincode::serialize(key)?;
-        let real_key = [self.prefix.clone(), key_bytes].concat();
+    fn indexes(&self) -> Vec<String> {
+        self.indexes
+            .iter()
+            .map(|(prefix, _)| String::from_utf8_lossy(prefix).into_owned())
+            .collect()
+    }
+
+    fn rebuild_index(&self, index: &str) -> Result<usize> {
+        let Some((prefix, extract)) = self.indexes.iter().find(|(p, _)| p == index.as_bytes())
+        else {
+            return Err(MoriError::Storage(format!("no index {index}")));
+        };
+        let _guard = self.inner.lock_commits();
+
+        let mut batch = WriteBatch::default();
+        let mode = IteratorMode::From(prefix, Direction::Forward);
+        for item in self.raw_unbounded(mode) {
+            let (key, _) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            batch.delete(key);
+        }
+        let mut built = 0;
+        for entry in self.iter() {
+            let (key, value) = entry?;
+            let key_bytes = key.to_key_bytes();
+            for index_key in extract(&key, &value) {
+                batch.put([prefix.as_slice(), &index_key, &key_bytes].concat(), b"");
+                built += 1;
+            }
+        }
+        self.inner.write(batch)?;
+        Ok(built)
+    }
 
-        let value = self.inner.get(real_key)?;
+    fn migrate_keys(&self, from: u16, batch: &mut WriteBatch) -> Result<usize> {
+        match from {
+            // keys were bincode encoded before the key codec
+            0 => self.rekey(batch, |bytes| Ok(Some(bincode::deserialize(bytes)?))),
+            _ => Err(MoriError::Storage(format!("unknown key layout {from}"))),
+        }
+    }
+}
 
-        Ok(value.is_some())
+impl DBMap<String, u16> {
+    /// Re-encodes the bincode keys of a schema versions table, which records
+    /// the key layout of every other table but not its own.
+    pub(crate) fn migrate_version_keys(&self) -> Result<usize> {
+        let mut batch = WriteBatch::default();
+        let moved = self.rekey(&mut batch, |bytes| match String::from_key_bytes(bytes) {
+            Ok(_) => Ok(None),
+            Err(_) => Ok(Some(bincode::deserialize(bytes)?)),
+        })?;
+        self.inner.write(batch)?;
+        Ok(moved)
     }
 }
 
@@ -212,7 +871,8 @@ impl<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned> DBMap<K,
 fn test_rocksdb_all_ops() {
     use rand::Rng;
 
-    let map = RocksDB::test_open_map::<String, String>("test").unwrap();
+    let db = Database::test_open().unwrap();
+    let map = db.map::<String, String>("test");
 
     let mut rng = rand::thread_rng();
 
@@ -253,7 +913,8 @@ fn test_rocksdb_all_ops() {
 fn test_insert_order() {
     use rand::Rng;
 
-    let map = RocksDB::test_open_map::<String, String>("test").unwrap();
+    let db = Database::test_open().unwrap();
+    let map = db.map::<String, String>("test");
 
     let mut rng = rand::thread_rng();
 
@@ -273,3 +934,222 @@ fn test_insert_order() {
 
     assert_eq!(kvs, got);
 }
+
+#[test]
+fn test_migrate_legacy_values() {
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Legacy {
+        id: u32,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Current {
+        id: u32,
+        name: String,
+    }
+
+    impl Versioned for Current {
+        const VERSION: u16 = 1;
+
+        fn upgrade(version: u16, bytes: &[u8]) -> Result<Self> {
+            assert_eq!(version, 0);
+            let Legacy { id } = bincode::deserialize(bytes)?;
+            Ok(Current {
+                id,
+                name: String::new(),
+            })
+        }
+    }
+
+    let db = Database::test_open().unwrap();
+    let map = db.map::<u32, Current>("legacy_values");
+    let versions = db.map::<String, u16>("legacy_versions");
+    versions
+        .insert(&"legacy_values/keys".to_string(), &KEY_LAYOUT)
+        .unwrap();
+
+    // a bare bincode value from before versioning
+    let key = map.real_key(&1);
+    let legacy = bincode::serialize(&Legacy { id: 1 }).unwrap();
+    map.inner.put(&key, &legacy).unwrap();
+    assert!(map.get(&1).is_err());
+
+    schema::Migrations::new(versions.clone())
+        .register(&map)
+        .run()
+        .unwrap();
+    let expected = Current {
+        id: 1,
+        name: String::new(),
+    };
+    assert_eq!(map.get(&1).unwrap(), Some(expected));
+    assert_eq!(versions.get(&"legacy_values".to_string()).unwrap(), Some(1));
+
+    // an enveloped version 0 value is still readable before it is migrated
+    let key = map.real_key(&2);
+    map.inner
+        .put(&key, &schema::encode(&Legacy { id: 2 }).unwrap())
+        .unwrap();
+    assert_eq!(map.get(&2).unwrap().unwrap().id, 2);
+    let mut batch = WriteBatch::default();
+    assert_eq!(map.migrate(Some(0), &mut batch).unwrap(), 1);
+    assert_eq!(batch.len(), 1);
+}
+
+#[test]
+fn test_transaction() {
+    let db = Database::test_open().unwrap();
+    let nodes = db.map::<u32, String>("txn_nodes");
+    let height = db.map::<String, u32>("txn_height");
+    nodes.insert(&1, &"one".to_string()).unwrap();
+
+    // writes across maps are visible to the transaction only until commit
+    let mut txn = nodes.transaction();
+    txn.insert(&nodes, &2, &"two".to_string()).unwrap();
+    txn.remove(&nodes, &1).unwrap();
+    txn.insert(&height, &"cur".to_string(), &7).unwrap();
+    assert_eq!(txn.get(&nodes, &2).unwrap(), Some("two".to_string()));
+    assert!(!txn.contain(&nodes, &1).unwrap());
+    assert_eq!(txn.get_all(&nodes).unwrap(), vec![(2, "two".to_string())]);
+    assert!(nodes.contain(&1).unwrap());
+
+    let savepoint = txn.savepoint();
+    txn.insert(&nodes, &3, &"three".to_string()).unwrap();
+    txn.rollback_to(savepoint);
+    txn.commit().unwrap();
+    assert_eq!(nodes.get_all().unwrap(), vec![(2, "two".to_string())]);
+    assert_eq!(height.get(&"cur".to_string()).unwrap(), Some(7));
+
+    // a value read by the transaction and changed meanwhile aborts the commit
+    let mut txn = nodes.transaction();
+    txn.update(&nodes, &2, |v| v.as_mut().unwrap().push('!'))
+        .unwrap();
+    nodes.insert(&2, &"deux".to_string()).unwrap();
+    assert!(matches!(txn.commit(), Err(MoriError::Conflict(_))));
+    assert_eq!(nodes.get(&2).unwrap(), Some("deux".to_string()));
+
+    nodes.update(&2, |v| *v = None).unwrap();
+    assert!(nodes.get_all().unwrap().is_empty());
+}
+
+#[test]
+fn test_ordered_keys() {
+    // the memory store must order and seek like RocksDB
+    for db in [Database::test_open().unwrap(), Database::memory()] {
+        let map = db.map::<(u32, String), u32>("ordered_keys");
+
+        let kvs = [(9, "b"), (10, "a"), (256, "a"), (9, "a"), (1, "z")]
+            .into_iter()
+            .map(|(h, s)| ((h, s.to_string()), h))
+            .collect::<Vec<_>>();
+        map.batch_insert(&kvs).unwrap();
+
+        let heights = |entries: Vec<((u32, String), u32)>| {
+            entries.into_iter().map(|(_, h)| h).collect::<Vec<_>>()
+        };
+        let all = map.iter().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(heights(all), vec![1, 9, 9, 10, 256]);
+        let rev = map.iter_rev().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(heights(rev), vec![256, 10, 9, 9, 1]);
+
+        let from = (9, String::new());
+        let to = (256, String::new());
+        let range = map.range(from..to).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(heights(range), vec![9, 9, 10]);
+        let nines = map.scan_prefix(&9u32).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(
+            nines.into_iter().map(|(k, _)| k.1).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+
+        assert_eq!(map.pop_front().unwrap(), Some(((1, "z".to_string()), 1)));
+        assert!(!map.contain(&(1, "z".to_string())).unwrap());
+    }
+}
+
+#[test]
+fn test_migrate_bincode_keys() {
+    let db = Database::test_open().unwrap();
+    let map = db.map::<u128, u32>("bincode_keys");
+    let versions = db.map::<String, u16>("bincode_versions");
+
+    for id in [1u128, 256] {
+        let key = [map.prefix.clone(), bincode::serialize(&id).unwrap()].concat();
+        map.inner
+            .put(&key, &schema::encode(&(id as u32)).unwrap())
+            .unwrap();
+    }
+    let legacy = [
+        versions.prefix.clone(),
+        bincode::serialize("bincode_keys").unwrap(),
+    ]
+    .concat();
+    versions
+        .inner
+        .put(&legacy, &schema::encode(&0u16).unwrap())
+        .unwrap();
+
+    schema::Migrations::new(versions.clone())
+        .register(&map)
+        .run()
+        .unwrap();
+    assert_eq!(map.get_all().unwrap(), vec![(1, 1), (256, 256)]);
+    assert_eq!(versions.get(&"bincode_keys".to_string()).unwrap(), Some(0));
+    assert_eq!(
+        versions.get(&"bincode_keys/keys".to_string()).unwrap(),
+        Some(KEY_LAYOUT)
+    );
+}
+
+#[test]
+fn test_secondary_index() {
+    let db = Database::test_open().unwrap();
+    let mut map = db.map::<u32, String>("indexed_words");
+    let by_len = map.add_index("len", |_, word: &String| vec![word.len() as u32]);
+
+    let words = ["a", "bb", "cc", "ddd"];
+    let kvs = (0..).zip(words.map(String::from)).collect::<Vec<_>>();
+    map.batch_insert(&kvs).unwrap();
+    assert_eq!(map.query_keys(&by_len, &2).unwrap(), vec![1, 2]);
+
+    // replacing and removing entries moves their index entries
+    map.insert(&1, &"b".to_string()).unwrap();
+    map.remove(&2).unwrap();
+    assert!(map.query_keys(&by_len, &2).unwrap().is_empty());
+    assert_eq!(
+        map.query(&by_len, &1).unwrap(),
+        vec![(0, "a".to_string()), (1, "b".to_string())]
+    );
+
+    let mut txn = map.transaction();
+    txn.insert(&map, &4, &"ee".to_string()).unwrap();
+    txn.remove(&map, &0).unwrap();
+    assert_eq!(txn.query_keys(&map, &by_len, &2).unwrap(), vec![4]);
+    assert_eq!(txn.query_keys(&map, &by_len, &1).unwrap(), vec![1]);
+    txn.commit().unwrap();
+    assert_eq!(map.query_keys(&by_len, &2).unwrap(), vec![4]);
+
+    assert_eq!(map.query_range_keys(&by_len, &1, &3).unwrap(), vec![1, 4]);
+    assert_eq!(map.query_range_keys(&by_len, &2, &9).unwrap(), vec![4, 3]);
+
+    assert_eq!(map.rebuild_index("index/indexed_words/len").unwrap(), 3);
+    assert_eq!(map.query_keys(&by_len, &3).unwrap(), vec![3]);
+}
+
+#[test]
+fn test_temp_database() {
+    let db = Database::test_open().unwrap();
+    let other = Database::test_open().unwrap();
+    let map = db.map::<u32, u32>("temp");
+    map.insert(&1, &1).unwrap();
+    assert_eq!(other.map::<u32, u32>("temp").get(&1).unwrap(), None);
+
+    // the directory lives as long as a map of the database
+    let path = db.0._temp.as_ref().unwrap().0.clone();
+    drop(db);
+    assert!(path.exists());
+    drop(map);
+    assert!(!path.exists());
+}
@@ -0,0 +1,210 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::cores::GameStateError;
+
+pub type Result<T, E = MoriError> = std::result::Result<T, E>;
+
+/// Why the values of a transition could not be read, `values` is `inputs`,
+/// `outputs` or `finalize`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DecodeError {
+    #[error("{function} expects {expected} {values}, got {got}")]
+    Length {
+        function: String,
+        values: String,
+        expected: usize,
+        got: usize,
+    },
+
+    #[error("{values} {index} of {function} cannot be decrypted with our view key")]
+    Undecryptable {
+        function: String,
+        values: String,
+        index: usize,
+    },
+
+    #[error("{values} {index} of {function} carries no value")]
+    NoValue {
+        function: String,
+        values: String,
+        index: usize,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MoriError {
+    #[error("chain rpc error: {0:#}")]
+    Chain(anyhow::Error),
+
+    #[error("ai backend error: {0}")]
+    Ai(String),
+
+    #[error("decode error: {0}")]
+    Decode(String),
+
+    #[error("decode error: {0}")]
+    Malformed(DecodeError),
+
+    #[error("storage error: {0}")]
+    Storage(String),
+
+    #[error("validation error: {0}")]
+    Validation(String),
+
+    #[error("execution error: {0}")]
+    Execution(String),
+
+    #[error("{0} not found")]
+    NotFound(String),
+
+    #[error("write conflict: {0}")]
+    Conflict(String),
+}
+
+impl MoriError {
+    /// Machine readable code the frontend switches on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            MoriError::Chain(_) => "chain_rpc",
+            MoriError::Ai(_) => "ai_backend",
+            MoriError::Decode(_) | MoriError::Malformed(_) => "decode",
+            MoriError::Storage(_) => "storage",
+            MoriError::Validation(_) => "validation",
+            MoriError::Execution(_) => "execution",
+            MoriError::NotFound(_) => "not_found",
+            MoriError::Conflict(_) => "conflict",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            MoriError::Chain(_) | MoriError::Ai(_) => StatusCode::BAD_GATEWAY,
+            MoriError::Decode(_) | MoriError::Malformed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            MoriError::Validation(_) => StatusCode::BAD_REQUEST,
+            MoriError::NotFound(_) => StatusCode::NOT_FOUND,
+            MoriError::Conflict(_) => StatusCode::CONFLICT,
+            MoriError::Storage(_) | MoriError::Execution(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether the error comes from a backend rather than from the input, the
+    /// same input may go through on a retry.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            MoriError::Chain(_)
+                | MoriError::Ai(_)
+                | MoriError::Storage(_)
+                | MoriError::Execution(_)
+                | MoriError::Conflict(_)
+        )
+    }
+
+    pub fn ai(e: impl std::fmt::Display) -> Self {
+        MoriError::Ai(e.to_string())
+    }
+
+    pub fn decode(e: impl std::fmt::Display) -> Self {
+        MoriError::Decode(e.to_string())
+    }
+
+    pub fn validation(e: impl std::fmt::Display) -> Self {
+        MoriError::Validation(e.to_string())
+    }
+
+    pub fn execution(e: impl std::fmt::Display) -> Self {
+        MoriError::Execution(e.to_string())
+    }
+}
+
+impl From<rocksdb::Error> for MoriError {
+    fn from(e: rocksdb::Error) -> Self {
+        MoriError::Storage(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for MoriError {
+    fn from(e: std::io::Error) -> Self {
+        MoriError::Storage(e.to_string())
+    }
+}
+
+impl From<bincode::Error> for MoriError {
+    fn from(e: bincode::Error) -> Self {
+        MoriError::Storage(e.to_string())
+    }
+}
+
+impl From<ureq::Error> for MoriError {
+    fn from(e: ureq::Error) -> Self {
+        MoriError::Ai(e.to_string())
+    }
+}
+
+impl From<GameStateError> for MoriError {
+    fn from(e: GameStateError) -> Self {
+        MoriError::Validation(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<DecodeError>,
+}
+
+impl IntoResponse for MoriError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        if status.is_server_error() {
+            tracing::error!("request failed: {}", self);
+        }
+        let body = ErrorResponse {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            detail: match &self {
+                MoriError::Malformed(e) => Some(e.clone()),
+                _ => None,
+            },
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+#[test]
+fn test_error_codes() {
+    let e = MoriError::from(GameStateError::InvalidLength(3));
+    assert_eq!(e.code(), "validation");
+    assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(e.to_string(), "validation error: expected 64 cells, got 3");
+
+    let e = MoriError::Chain(anyhow::anyhow!("connection refused"));
+    assert_eq!(e.code(), "chain_rpc");
+    assert_eq!(e.status_code(), StatusCode::BAD_GATEWAY);
+    assert!(e.is_transient());
+    assert_eq!(
+        MoriError::NotFound("node 1".into()).to_string(),
+        "node 1 not found"
+    );
+
+    let e = MoriError::Malformed(DecodeError::Length {
+        function: "move_to_next".into(),
+        values: "inputs".into(),
+        expected: 5,
+        got: 1,
+    });
+    assert_eq!(e.code(), "decode");
+    assert!(!e.is_transient());
+    assert_eq!(
+        e.to_string(),
+        "decode error: move_to_next expects 5 inputs, got 1"
+    );
+}
@@ -1,6 +1,14 @@
 use aleo_rust::{Block, Network, ProgramID};
+use serde::{Deserialize, Serialize};
 use snarkvm_ledger::Transition;
 
+/// Position of a transition on chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockMeta {
+    pub height: u32,
+    pub timestamp: i64,
+}
+
 #[derive(Clone, Debug)]
 pub struct TransitionFilter<N: Network> {
     program_ids: Vec<ProgramID<N>>,
@@ -26,6 +34,17 @@ impl<N: Network> TransitionFilter<N> {
     }
 
     pub fn filter_block(&self, block: Block<N>) -> Vec<Transition<N>> {
+        self.filter_block_with_meta(block)
+            .into_iter()
+            .map(|(_, t)| t)
+            .collect()
+    }
+
+    pub fn filter_block_with_meta(&self, block: Block<N>) -> Vec<(BlockMeta, Transition<N>)> {
+        let meta = BlockMeta {
+            height: block.height(),
+            timestamp: block.timestamp(),
+        };
         let ts = block
             .transactions()
             .clone()
@@ -39,6 +58,30 @@ impl<N: Network> TransitionFilter<N> {
                 let program_id = t.program_id();
                 self.program_ids.contains(program_id)
             })
+            .map(|t| (meta, t))
             .collect()
     }
 }
+
+#[test]
+fn test_filter_block_with_meta() {
+    use aleo_rust::Testnet3;
+    use snarkvm_utilities::FromBytes;
+    use std::str::FromStr;
+
+    let genesis = Block::<Testnet3>::from_bytes_le(Testnet3::genesis_bytes()).unwrap();
+    let credits = ProgramID::from_str("credits.aleo").unwrap();
+    let transitions = TransitionFilter::new()
+        .add_program(credits)
+        .filter_block_with_meta(genesis.clone());
+    assert!(!transitions.is_empty());
+    assert!(transitions
+        .iter()
+        .all(|(meta, t)| meta.height == 0 && *t.program_id() == credits));
+
+    let mori = ProgramID::from_str(crate::program::PROGRAM_ID).unwrap();
+    let transitions = TransitionFilter::new()
+        .add_program(mori)
+        .filter_block_with_meta(genesis);
+    assert!(transitions.is_empty());
+}
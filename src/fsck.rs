@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+use aleo_rust::Network;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cores::{GameNode, GameState, Side},
+    error::Result,
+    Mori,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FsckIssue {
+    MissingParent,
+    IllegalMove,
+    BoardMismatch,
+    StatusMismatch,
+    ChainMismatch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FsckProblem {
+    pub node_id: u128,
+    pub issue: FsckIssue,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct FsckReport {
+    pub checked: usize,
+    pub problems: Vec<FsckProblem>,
+    pub repaired: Vec<u128>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct FsckOptions {
+    /// Also compare every node against the chain mapping, one RPC per node.
+    #[serde(default)]
+    pub chain: bool,
+    /// Re-fetch the nodes with problems from the chain and the AI.
+    #[serde(default)]
+    pub repair: bool,
+}
+
+/// Whether `child` is `parent` after the voters play `mov` and the AI answers,
+/// or passes when it has no move.
+pub fn follows(parent: &GameState, mov: u8, child: &GameState) -> bool {
+    let Some(after) = parent.play(mov, Side::Black) else {
+        return false;
+    };
+    after == *child
+        || after
+            .legal_moves(Side::White)
+            .into_iter()
+            .any(|ai| after.play(ai, Side::White).as_ref() == Some(child))
+}
+
+/// Checks a node against its parent, `parent` is `None` for roots.
+pub fn check_node(node: &GameNode, parent: Option<&GameNode>) -> Vec<(FsckIssue, String)> {
+    let mut issues = vec![];
+    if let Err(e) = node.state.check_status(node.game_status) {
+        issues.push((FsckIssue::StatusMismatch, e.to_string()));
+    }
+    if node.is_root() {
+        return issues;
+    }
+
+    let Some(parent) = parent else {
+        issues.push((
+            FsckIssue::MissingParent,
+            format!("parent {} is not stored", node.from.node_id),
+        ));
+        return issues;
+    };
+    let mov = node.from.mov;
+    if !parent.valid_movs.contains(&mov) {
+        issues.push((
+            FsckIssue::IllegalMove,
+            format!(
+                "move {mov} is not a valid move of parent {}",
+                parent.node_id
+            ),
+        ));
+    }
+    if !follows(&parent.state, mov, &node.state) {
+        issues.push((
+            FsckIssue::BoardMismatch,
+            format!(
+                "board does not follow from parent {} and move {mov}",
+                parent.node_id
+            ),
+        ));
+    }
+    issues
+}
+
+impl<N: Network> Mori<N> {
+    pub fn fsck(&self, options: FsckOptions) -> Result<FsckReport> {
+        let nodes = self
+            .mori_nodes
+            .get_all()?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let mut report = FsckReport {
+            checked: nodes.len(),
+            ..Default::default()
+        };
+
+        for node in nodes.values() {
+            let parent = nodes.get(&node.from.node_id);
+            for (issue, detail) in check_node(node, parent) {
+                report.problems.push(FsckProblem {
+                    node_id: node.node_id,
+                    issue,
+                    detail,
+                });
+            }
+        }
+
+        if options.chain {
+            for mismatch in self.reconcile_nodes()? {
+                report.problems.push(FsckProblem {
+                    node_id: mismatch.node_id,
+                    issue: FsckIssue::ChainMismatch,
+                    detail: mismatch.detail,
+                });
+            }
+        }
+        report.problems.sort_by_key(|p| p.node_id);
+        tracing::info!(
+            "fsck checked {} nodes, {} problems",
+            report.checked,
+            report.problems.len()
+        );
+
+        if options.repair {
+            report.repaired = self.repair_nodes(&report.problems, &nodes)?;
+        }
+        Ok(report)
+    }
+
+    /// Replaces broken nodes by the chain value and a fresh analysis, and
+    /// fetches missing parents. Votes already counted on a node are kept.
+    fn repair_nodes(
+        &self,
+        problems: &[FsckProblem],
+        nodes: &HashMap<u128, GameNode>,
+    ) -> Result<Vec<u128>> {
+        let mut targets = problems
+            .iter()
+            .map(|p| match p.issue {
+                FsckIssue::MissingParent => nodes[&p.node_id].from.node_id,
+                _ => p.node_id,
+            })
+            .collect::<Vec<_>>();
+        targets.sort();
+        targets.dedup();
+
+        // one read of every target, the mirror keeps them for the analyses
+        let height = self.synced_height()?;
+        let mut txn = self.transaction();
+        let chain = self.fetch_chain_nodes(&mut txn, &targets, height)?;
+        txn.commit()?;
+
+        let mut repaired = vec![];
+        for (node_id, chain) in chain {
+            let mut txn = self.transaction();
+            let analysed = chain.and_then(|node| self.analyse_node(&mut txn, node));
+            let mut node = match analysed {
+                Ok(node) => node,
+                Err(e) => {
+                    tracing::error!("fsck could not repair node {node_id}: {e}");
+                    continue;
+                }
+            };
+            if let Some(old) = txn.get(&self.mori_nodes, &node_id)? {
+                node.votes = old.votes;
+            }
+            txn.insert(&self.mori_nodes, &node_id, &node)?;
+            self.index_position(&mut txn, &node)?;
+            if let Err(e) = txn.commit() {
+                tracing::error!("fsck could not repair node {node_id}: {e}");
+                continue;
+            }
+            tracing::info!("fsck repaired node {node_id}");
+            repaired.push(node_id);
+        }
+        Ok(repaired)
+    }
+}
+
+#[test]
+fn test_check_node() {
+    use crate::cores::NodeEdge;
+
+    let zero = GameState::zero();
+    let root = GameNode {
+        from: NodeEdge { node_id: 0, mov: 0 },
+        valid_movs: zero.legal_moves(Side::Black),
+        ..crate::test_node(1, 0)
+    };
+    assert!(check_node(&root, None).is_empty());
+
+    // f5 then the AI answers d6
+    let after = zero.play(29, Side::Black).unwrap();
+    let state = after.play(19, Side::White).unwrap();
+    let child = GameNode {
+        state,
+        from: NodeEdge {
+            node_id: 1,
+            mov: 29,
+        },
+        ..crate::test_node(2, 1)
+    };
+    assert!(check_node(&child, Some(&root)).is_empty());
+
+    let issues = check_node(&child, None);
+    assert_eq!(issues[0].0, FsckIssue::MissingParent);
+
+    let wrong = GameNode {
+        from: NodeEdge { node_id: 1, mov: 0 },
+        ..child.clone()
+    };
+    let issues = check_node(&wrong, Some(&root))
+        .into_iter()
+        .map(|(issue, _)| issue)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        issues,
+        vec![FsckIssue::IllegalMove, FsckIssue::BoardMismatch]
+    );
+}
+
+#[test]
+fn test_fsck_report() {
+    use crate::cores::NodeEdge;
+
+    let (mori, _rx) = Mori::<aleo_rust::Testnet3>::test_open().unwrap();
+    let zero = GameState::zero();
+    let orphan = GameNode {
+        state: zero.play(29, Side::Black).unwrap(),
+        from: NodeEdge {
+            node_id: 1,
+            mov: 29,
+        },
+        ..crate::test_node(2, 1)
+    };
+    mori.mori_nodes.insert(&2, &orphan).unwrap();
+
+    let report = mori.fsck(FsckOptions::default()).unwrap();
+    assert_eq!(report.checked, 1);
+    assert_eq!(report.problems.len(), 1);
+    assert_eq!(report.problems[0].issue, FsckIssue::MissingParent);
+    assert!(report.repaired.is_empty());
+
+    // every fixture has its own database
+    let (other, _rx) = Mori::<aleo_rust::Testnet3>::test_open().unwrap();
+    assert_eq!(other.fsck(FsckOptions::default()).unwrap().checked, 0);
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeSet;
+
+use aleo_rust::Network;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cores::{GameNode, GameState},
+    db::DBTransaction,
+    error::{MoriError, Result},
+    notation::line_transcript,
+    Mori,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameOutcome {
+    BlackWins,
+    WhiteWins,
+    Draw,
+}
+
+impl GameOutcome {
+    /// Decides the winner by counting the discs of the final board.
+    pub fn from_board(state: &GameState) -> Self {
+        let (black, white) = state.count();
+        match black.cmp(&white) {
+            std::cmp::Ordering::Greater => GameOutcome::BlackWins,
+            std::cmp::Ordering::Less => GameOutcome::WhiteWins,
+            std::cmp::Ordering::Equal => GameOutcome::Draw,
+        }
+    }
+
+    /// Whether the voters won, they always play black against the AI.
+    /// `None` is a draw.
+    pub fn voters_won(&self) -> Option<bool> {
+        match self {
+            GameOutcome::BlackWins => Some(true),
+            GameOutcome::WhiteWins => Some(false),
+            GameOutcome::Draw => None,
+        }
+    }
+}
+
+/// A finished game line, archived out of `mori_nodes`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FinishedGame {
+    pub root_id: u128,
+    pub leaf_id: u128,
+    pub outcome: GameOutcome,
+    pub score: (u8, u8), // (black, white)
+    pub game_status: i8,
+    pub final_state: GameState,
+    // node ids from the root to the leaf, and the voted move that led to each of them
+    pub nodes: Vec<u128>,
+    pub moves: Vec<u8>,
+    // voter and AI moves from the opening, see `notation::line_transcript`
+    pub transcript: Vec<u8>,
+    pub participants: Vec<String>,
+}
+
+impl FinishedGame {
+    /// Builds the archive of a line, `line` runs from the root to the terminal node.
+    pub fn from_line(line: &[GameNode]) -> Option<Self> {
+        let (root, leaf) = (line.first()?, line.last()?);
+        let participants = line
+            .iter()
+            .flat_map(|n| n.votes.iter().map(|v| v.sender.to_string()))
+            .collect::<BTreeSet<_>>();
+
+        Some(Self {
+            root_id: root.node_id,
+            leaf_id: leaf.node_id,
+            outcome: GameOutcome::from_board(&leaf.state),
+            score: leaf.state.count(),
+            game_status: leaf.game_status,
+            final_state: leaf.state,
+            nodes: line.iter().map(|n| n.node_id).collect(),
+            moves: line.iter().skip(1).map(|n| n.from.mov).collect(),
+            transcript: line_transcript(line).unwrap_or_else(|e| {
+                tracing::warn!("game line {} has no transcript: {:?}", leaf.node_id, e);
+                vec![]
+            }),
+            participants: participants.into_iter().collect(),
+        })
+    }
+}
+
+impl<N: Network> Mori<N> {
+    /// Finalizes a terminal node: settles the result, archives its line and
+    /// removes the nodes no other live branch goes through. The outcome is
+    /// counted on the board unless one is given, as for a forfeit.
+    pub fn finalize_game(
+        &self,
+        txn: &mut DBTransaction,
+        leaf: &GameNode,
+        outcome: Option<GameOutcome>,
+    ) -> Result<FinishedGame> {
+        let line = self.game_line(txn, leaf)?;
+        let mut game =
+            FinishedGame::from_line(&line).ok_or(MoriError::validation("empty game line"))?;
+        if let Some(outcome) = outcome {
+            game.outcome = outcome;
+        }
+        self.archive_game(txn, &line, game)
+    }
+
+    fn archive_game(
+        &self,
+        txn: &mut DBTransaction,
+        line: &[GameNode],
+        game: FinishedGame,
+    ) -> Result<FinishedGame> {
+        tracing::info!(
+            "game line {} finished {:?} {}:{}",
+            game.leaf_id,
+            game.outcome,
+            game.score.0,
+            game.score.1
+        );
+
+        // a replayed block finalizes the same line again
+        if !txn.contain(&self.finished_games, &game.leaf_id)? {
+            self.settle_players(txn, &game.participants, game.outcome)?;
+        }
+        txn.insert(&self.finished_games, &game.leaf_id, &game)?;
+
+        // walk up from the leaf while the node is not shared with another branch
+        let mut removed = vec![];
+        for node in line.iter().rev() {
+            let children = txn.query_keys(&self.mori_nodes, &self.children, &node.node_id)?;
+            if children.iter().any(|c| !removed.contains(c)) {
+                break;
+            }
+            removed.push(node.node_id);
+        }
+        txn.batch_remove(&self.mori_nodes, &removed)?;
+        tracing::info!(
+            "archived {} nodes of game line {}",
+            removed.len(),
+            game.leaf_id
+        );
+
+        Ok(game)
+    }
+
+    /// The nodes from the root of the game to `leaf`.
+    pub fn game_line(&self, txn: &mut DBTransaction, leaf: &GameNode) -> Result<Vec<GameNode>> {
+        let mut line = vec![leaf.clone()];
+        let mut cur = leaf.clone();
+        while !cur.is_root() {
+            match txn.get(&self.mori_nodes, &cur.from.node_id)? {
+                Some(parent) => {
+                    line.push(parent.clone());
+                    cur = parent;
+                }
+                None => break,
+            }
+        }
+        line.reverse();
+        Ok(line)
+    }
+
+    pub fn get_finished_games(&self) -> Result<Vec<FinishedGame>> {
+        let mut games = self
+            .finished_games
+            .get_all()?
+            .into_iter()
+            .map(|(_, g)| g)
+            .collect::<Vec<_>>();
+        games.sort_by_key(|g| (g.root_id, g.leaf_id));
+        Ok(games)
+    }
+
+    pub fn get_finished_game(&self, leaf_id: u128) -> Result<Option<FinishedGame>> {
+        self.finished_games.get(&leaf_id)
+    }
+}
+
+#[test]
+fn test_finished_game_from_line() {
+    use crate::cores::{NodeEdge, Vote};
+
+    let node = |node_id, parent, mov, voters: &[&str]| GameNode {
+        node_id,
+        state: GameState::zero(),
+        from: NodeEdge {
+            node_id: parent,
+            mov,
+        },
+        game_status: 0,
+        valid_movs: vec![],
+        votes: voters
+            .iter()
+            .map(|s| Vote {
+                sender: (*s).into(),
+                node_id,
+                mov: 0,
+            })
+            .collect(),
+    };
+    let mut leaf = node(3, 2, 26, &[]);
+    leaf.game_status = 1;
+    // black fills the first row on top of the opening discs
+    let mut board = GameState::zero().to_vec_i8();
+    board[..8].fill(1);
+    leaf.state = GameState::from_vec_i8(&board).unwrap();
+    let line = vec![node(1, 0, 0, &["b", "a"]), node(2, 1, 19, &["a"]), leaf];
+
+    let game = FinishedGame::from_line(&line).unwrap();
+    assert_eq!(game.root_id, 1);
+    assert_eq!(game.leaf_id, 3);
+    assert_eq!(game.nodes, vec![1, 2, 3]);
+    assert_eq!(game.moves, vec![19, 26]);
+    assert_eq!(game.participants, vec!["a".to_string(), "b".to_string()]);
+    assert_eq!(game.score, (10, 2));
+    assert_eq!(game.outcome, GameOutcome::BlackWins);
+    assert_eq!(game.outcome.voters_won(), Some(true));
+}
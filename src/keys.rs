@@ -0,0 +1,134 @@
+use crate::error::{MoriError, Result};
+
+/// Key encoding whose byte order is the order of the keys.
+///
+/// Integers are big-endian and strings are length-prefixed, so the encoding of
+/// a tuple starts with the encoding of its leading components and a scan over
+/// that prefix finds every key sharing them.
+pub trait KeyCodec: Sized {
+    fn encode_key(&self, out: &mut Vec<u8>);
+
+    /// Decodes a key from the front of `bytes` and advances past it.
+    fn decode_key(bytes: &mut &[u8]) -> Result<Self>;
+
+    fn to_key_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode_key(&mut out);
+        out
+    }
+
+    /// Decodes a key that spans all of `bytes`.
+    fn from_key_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let key = Self::decode_key(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(MoriError::Storage(format!(
+                "{} trailing bytes after key",
+                bytes.len()
+            )));
+        }
+        Ok(key)
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if bytes.len() < len {
+        return Err(MoriError::Storage(format!(
+            "key truncated, expected {len} bytes, got {}",
+            bytes.len()
+        )));
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(head)
+}
+
+macro_rules! uint_key {
+    ($($ty:ty),*) => {$(
+        impl KeyCodec for $ty {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+
+            fn decode_key(bytes: &mut &[u8]) -> Result<Self> {
+                let head = take(bytes, std::mem::size_of::<$ty>())?;
+                Ok(<$ty>::from_be_bytes(head.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+
+uint_key!(u8, u16, u32, u64, u128);
+
+impl KeyCodec for i8 {
+    // flipping the sign bit orders negative values first
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8 ^ 0x80);
+    }
+
+    fn decode_key(bytes: &mut &[u8]) -> Result<Self> {
+        Ok((u8::decode_key(bytes)? ^ 0x80) as i8)
+    }
+}
+
+impl KeyCodec for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode_key(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode_key(bytes: &mut &[u8]) -> Result<Self> {
+        let len = u32::decode_key(bytes)? as usize;
+        let head = take(bytes, len)?;
+        String::from_utf8(head.to_vec()).map_err(|e| MoriError::Storage(e.to_string()))
+    }
+}
+
+macro_rules! tuple_key {
+    ($($name:ident),*) => {
+        impl<$($name: KeyCodec),*> KeyCodec for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($($name,)*) = self;
+                $($name.encode_key(out);)*
+            }
+
+            fn decode_key(bytes: &mut &[u8]) -> Result<Self> {
+                Ok(($($name::decode_key(bytes)?,)*))
+            }
+        }
+    };
+}
+
+tuple_key!(A, B);
+tuple_key!(A, B, C);
+tuple_key!(A, B, C, D);
+
+#[test]
+fn test_key_order() {
+    let mut ids = vec![0u128, 1, 255, 256, 1 << 64, u128::MAX];
+    let mut encoded = ids.iter().map(|id| id.to_key_bytes()).collect::<Vec<_>>();
+    encoded.sort();
+    ids.sort();
+    let decoded = encoded
+        .iter()
+        .map(|b| u128::from_key_bytes(b).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, ids);
+
+    let key = (7u128, "at1transition".to_string(), 2u8);
+    let bytes = key.to_key_bytes();
+    assert!(bytes.starts_with(&7u128.to_key_bytes()));
+    assert_eq!(<(u128, String, u8)>::from_key_bytes(&bytes).unwrap(), key);
+
+    // heights order before the string that follows them
+    let a = (9u32, "zz".to_string()).to_key_bytes();
+    let b = (10u32, "aa".to_string()).to_key_bytes();
+    assert!(a < b);
+
+    let statuses = [-1i8, 0, 1].map(|s| s.to_key_bytes());
+    assert!(statuses.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(i8::from_key_bytes(&statuses[0]).unwrap(), -1);
+
+    assert!(u64::from_key_bytes(&[1, 2, 3]).is_err());
+    assert!(u8::from_key_bytes(&[1, 2]).is_err());
+}
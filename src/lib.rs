@@ -1,26 +1,64 @@
-use anyhow::anyhow;
-use cores::{GameNode, MovRequest, RestResponse, Vote};
+use cores::{
+    GameNode, MovRequest, Rejection, RestResponse, SkippedVote, Vote, VoteRecord, VoteSkipReason,
+};
 use once_cell::sync::OnceCell;
-use snarkvm_ledger::{Input, Transition};
-use std::str::FromStr;
+use snarkvm_ledger::{Output, Transition};
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use aleo_rust::{
-    AleoAPIClient, Network, Plaintext, PrivateKey, ProgramID, ProgramManager, ViewKey
+    AleoAPIClient, Network, Plaintext, PrivateKey, ProgramID, ProgramManager, ViewKey,
+};
+use db::{DBMap, DBTransaction, Database, Index};
+use error::{MoriError, Result};
+use filter::{BlockMeta, TransitionFilter};
+use games::FinishedGame;
+use mirror::{move_to_next_effect, open_game_effect, MirrorSource, MirroredNode};
+use players::PlayerStats;
+use pool::{FeeAllocator, FeeLease, WorkerPool};
+use program::{decode_transition, Function, MoveToNextInputs, OpenGameInputs};
+use prune::PruneMetrics;
+use scheduler::{ScheduledOpen, SchedulerState};
+use schema::Migrations;
+use timers::TurnTimers;
+
+use crate::{
+    cores::GameState,
+    utils::{FromAleoPlaintext, FromAleoRecord},
 };
-use db::{DBMap, RocksDB};
-use filter::TransitionFilter;
-
-use crate::{cores::GameState, utils::handle_u128_plaintext};
 
+pub mod backup;
 pub mod cores;
 pub mod db;
+pub mod error;
 pub mod filter;
+pub mod fsck;
+pub mod games;
+pub mod keys;
+pub mod mirror;
+pub mod notation;
+pub mod players;
+pub mod pool;
+pub mod program;
+pub mod prune;
+pub mod render;
+pub mod scheduler;
+pub mod schema;
+pub mod store;
+pub mod symmetry;
+pub mod timers;
 pub mod utils;
 
 pub const ALEO_NETWORK: &str = "testnet3";
 static ALEO_CONTRACT: OnceCell<String> = OnceCell::new();
 pub const FEE_NUM: u64 = 40000; // 0.04 aleo
+const RECONCILE_INTERVAL_SECS: u64 = 600;
 
 #[derive(Clone)]
 pub struct Mori<N: Network> {
@@ -32,43 +70,103 @@ pub struct Mori<N: Network> {
     ai_dest: String,
     ai_token: String,
 
+    pk: PrivateKey<N>,
     vk: ViewKey<N>,
     network_key: String, // <dest>-<pk>
 
+    workers: usize,
+    fees: FeeAllocator<N>,
+
+    db: Database,
     network_height: DBMap<String, u32>,
-    mori_nodes: DBMap<u128, GameNode>, // <node_id, node>
+    mori_nodes: DBMap<u128, GameNode>,            // <node_id, node>
+    children: Index<u128, u128, GameNode>,        // <parent_id, node_id>
+    nodes_by_status: Index<i8, u128, GameNode>,   // <game_status, node_id>
+    votes: DBMap<(u128, String, u8), VoteRecord>, // <(node_id, transition_id, output), vote>
+    votes_by_sender: Index<String, (u128, String, u8), VoteRecord>, // <sender, vote key>
+    votes_by_height: Index<u32, (u128, String, u8), VoteRecord>, // <block_height, vote key>
+    skipped_votes: DBMap<(String, u8), SkippedVote>, // <(transition_id, output), skip>
+    skipped_by_height: Index<u32, (String, u8), SkippedVote>, // <block_height, skip key>
+    players: DBMap<String, PlayerStats>,          // <address, stats>
+    finished_games: DBMap<u128, FinishedGame>,    // <leaf_id, game>
+    positions: DBMap<u64, Vec<u128>>,             // <position hash, node_ids>
+    rejections: DBMap<String, Rejection>,         // <execution or transition id, rejection>
+    chain_nodes: DBMap<u128, MirroredNode>,       // <node_id, on-chain `nodes` entry>
+    node_births: DBMap<u128, BlockMeta>,          // <node_id, block that created the node>
+    majority_credits: DBMap<u128, u8>,            // <parent_id, move its majority was credited for>
+    scheduled_opens: DBMap<u128, ScheduledOpen>,  // <open request id, open not indexed yet>
+    timed_out_turns: DBMap<u128, i64>,            // <node_id, when its timeout fallback was played>
+
+    prune_metrics: Arc<Mutex<PruneMetrics>>,
+    scheduler: Arc<Mutex<SchedulerState>>,
+    turn_timers: Arc<Mutex<TurnTimers>>,
+    // set once sync reached the latest height after startup
+    synced: Arc<AtomicBool>,
 }
 
 impl<N: Network> Mori<N> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        db: Database,
         aleo_rpc: Option<String>,
         pk: PrivateKey<N>,
         tx: Sender<Execution>,
         program_name: String,
         ai_dest: String,
         ai_token: String,
-    ) -> anyhow::Result<Self> {
+        workers: usize,
+    ) -> Result<Self> {
         let aleo_client = match aleo_rpc {
-            Some(aleo_rpc) => AleoAPIClient::new(&aleo_rpc, ALEO_NETWORK)?,
+            Some(aleo_rpc) => {
+                AleoAPIClient::new(&aleo_rpc, ALEO_NETWORK).map_err(MoriError::Chain)?
+            }
             None => AleoAPIClient::testnet3(),
         };
         let network_key = format!("{:?}-{}", aleo_client.network_id(), pk);
         tracing::info!("your private key is: {pk}, network key is {network_key}");
 
         tracing::info!("program name is {program_name}");
-        ALEO_CONTRACT.set(program_name).map_err(|e| anyhow!(e))?;
-
-        let vk = ViewKey::try_from(&pk)?;
-        let pm = ProgramManager::new(Some(pk), None, Some(aleo_client.clone()), None, true)?;
-        let filter =
-            TransitionFilter::new().add_program(ProgramID::from_str(ALEO_CONTRACT.get().unwrap())?);
+        let contract = ALEO_CONTRACT.get_or_init(|| program_name.clone());
+        if *contract != program_name {
+            return Err(MoriError::validation(format!(
+                "program name already set to {contract}"
+            )));
+        }
 
-        let mori_nodes = RocksDB::open_map("mori_nodes")?;
-        let network_height = RocksDB::open_map("network")?;
+        let vk = ViewKey::try_from(&pk).map_err(MoriError::validation)?;
+        let pm = ProgramManager::new(Some(pk), None, Some(aleo_client.clone()), None, true)
+            .map_err(MoriError::execution)?;
+        let filter = TransitionFilter::new().add_program(
+            ProgramID::from_str(ALEO_CONTRACT.get().unwrap()).map_err(MoriError::validation)?,
+        );
+
+        let mut mori_nodes: DBMap<u128, GameNode> = db.map("mori_nodes");
+        let children = mori_nodes.add_index("children", |_, node| match node.is_root() {
+            true => vec![],
+            false => vec![node.from.node_id],
+        });
+        let nodes_by_status = mori_nodes.add_index("status", |_, node| vec![node.game_status]);
+        let network_height = db.map("network");
+        let mut votes: DBMap<(u128, String, u8), VoteRecord> = db.map("votes");
+        let votes_by_sender =
+            votes.add_index("sender", |_, record| vec![record.vote.sender.to_string()]);
+        let votes_by_height = votes.add_index("height", |_, record| vec![record.block_height]);
+        let mut skipped_votes: DBMap<(String, u8), SkippedVote> = db.map("skipped_votes");
+        let skipped_by_height =
+            skipped_votes.add_index("height", |_, skipped| vec![skipped.block_height]);
+        let players = db.map("players");
+        let finished_games = db.map("finished_games");
+        let positions = db.map("positions");
+        let rejections = db.map("rejections");
+        let chain_nodes = db.map("chain_nodes");
+        let node_births = db.map("node_births");
+        let majority_credits = db.map("majority_credits");
+        let scheduled_opens = db.map("scheduled_opens");
+        let timed_out_turns = db.map("timed_out_turns");
 
         let ai_token = format!(" Bearar {}", ai_token);
 
-        Ok(Self {
+        let mori = Self {
             pm,
             aleo_client,
             filter,
@@ -77,93 +175,293 @@ impl<N: Network> Mori<N> {
             ai_token,
 
             tx,
+            pk,
             vk,
+            workers: workers.max(1),
+            fees: FeeAllocator::new(),
+            db,
             mori_nodes,
+            children,
+            nodes_by_status,
+            votes,
+            votes_by_sender,
+            votes_by_height,
+            skipped_votes,
+            skipped_by_height,
+            players,
+            finished_games,
+            positions,
+            rejections,
+            chain_nodes,
+            node_births,
+            majority_credits,
+            scheduled_opens,
+            timed_out_turns,
             network_height,
             network_key,
-        })
+            prune_metrics: Arc::new(Mutex::new(PruneMetrics::default())),
+            scheduler: Arc::new(Mutex::new(SchedulerState::default())),
+            turn_timers: Arc::new(Mutex::new(TurnTimers::default())),
+            synced: Arc::new(AtomicBool::new(false)),
+        };
+        mori.migrate()?;
+        Ok(mori)
+    }
+
+    /// A `Mori` on a fresh in-memory database with a random key, for tests.
+    /// Nothing is sent to the chain or the AI, executions stay in the receiver.
+    #[cfg(test)]
+    pub(crate) fn test_open() -> Result<(Self, Receiver<Execution>)> {
+        let pk = PrivateKey::new(&mut rand::thread_rng()).map_err(MoriError::validation)?;
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let mori = Self::new(
+            Database::memory(),
+            None,
+            pk,
+            tx,
+            program::PROGRAM_ID.to_string(),
+            "http://127.0.0.1:0".to_string(),
+            String::new(),
+            1,
+        )?;
+        Ok((mori, rx))
     }
 
-    pub fn sync(&self) -> anyhow::Result<()> {
+    /// Brings every table to the current schema version.
+    fn migrate(&self) -> Result<()> {
+        Migrations::new(self.db.map("schema"))
+            .register(&self.network_height)
+            .register(&self.mori_nodes)
+            .register(&self.votes)
+            .register(&self.skipped_votes)
+            .register(&self.players)
+            .register(&self.finished_games)
+            .register(&self.positions)
+            .register(&self.rejections)
+            .register(&self.chain_nodes)
+            .register(&self.node_births)
+            .register(&self.majority_credits)
+            .register(&self.scheduled_opens)
+            .register(&self.timed_out_turns)
+            .run()
+    }
+
+    pub fn sync(&self) -> Result<()> {
         let cur = self.network_height.get(&self.network_key)?.unwrap_or(0);
-        let latest = self.aleo_client.latest_height()?;
+        let latest = self.aleo_client.latest_height().map_err(MoriError::Chain)?;
         tracing::debug!("Requesting aleo blocks from {} to {}", cur, latest);
         const BATCH_SIZE: usize = 45;
 
-        let ts_handler = move |transitions: Vec<Transition<N>>| {
-            for t in transitions {
-                match t.function_name().to_string().as_str() {
-                    "vote" => self.handle_vote(t)?,
-                    "move_to_next" => self.handle_move(t)?,
-                    "open_game" => self.handle_open(t)?,
-                    _ => {}
-                }
-            }
-            Ok::<_, anyhow::Error>(())
-        };
-
         for start in (cur..latest).step_by(BATCH_SIZE) {
             let end = (start + BATCH_SIZE as u32).min(latest);
             tracing::warn!("Fetched aleo blocks from {} to {}", start, end);
-            let transitions = self
+            let blocks = self
                 .aleo_client
-                .get_blocks(start, end)?
-                .into_iter()
-                .flat_map(|b| self.filter.filter_block(b))
-                .collect::<Vec<Transition<N>>>();
-            if let Err(e) = ts_handler(transitions) {
-                tracing::error!("handle transitions error: {:?}", e);
+                .get_blocks(start, end)
+                .map_err(MoriError::Chain)?;
+            for block in blocks {
+                let transitions = self.filter.filter_block_with_meta(block);
+                let Some((meta, _)) = transitions.first() else {
+                    continue;
+                };
+                // the block's effects and the checkpoint past it land together
+                let next = meta.height + 1;
+                let mut txn = self.transaction();
+                let execs = self.handle_transitions(&mut txn, transitions)?;
+                txn.insert(&self.network_height, &self.network_key, &next)?;
+                txn.commit()?;
+                // only moves of a committed block are played
+                for exec in execs {
+                    self.tx.blocking_send(exec).map_err(MoriError::execution)?;
+                }
             }
+            self.network_height.insert(&self.network_key, &end)?;
         }
 
-        self.network_height.insert(&self.network_key, &latest)?;
         tracing::info!("Synced aleo blocks from {} to {}", cur, latest);
+        self.synced.store(true, Ordering::Release);
         Ok(())
     }
 
-    pub fn execute_program(self, mut rx: Receiver<Execution>) -> anyhow::Result<()> {
-        let handler = move |exec| {
-            tracing::warn!("received execution: {:?}", exec);
-            let (function, inputs) = match exec {
-                Execution::MoveToNext(mov) => {
-                    let game_state = GameState::from_vec_i8(&mov.state);
-                    let parent_id = mov.parent_id.ok_or(anyhow!("no parent id"))?;
-                    let inputs = vec![
-                        format!("{}u128", parent_id),
-                        format!("{}u128", mov.node_id),
-                        format!("{}u128", game_state.raw()),
-                        format!("{}i8", mov.game_status),
-                        format!("{}u8", mov.human_move.expect("no human mov")),
-                    ];
-                    ("move_to_next", inputs)
+    /// Applies the transitions of one block, returns the executions to send
+    /// once `txn` is committed. A transition that cannot be decoded or applied
+    /// leaves nothing in `txn` but its rejection. Backend errors abort the
+    /// block instead, so the next sync replays it.
+    fn handle_transitions(
+        &self,
+        txn: &mut DBTransaction,
+        transitions: Vec<(BlockMeta, Transition<N>)>,
+    ) -> Result<Vec<Execution>> {
+        let mut execs = vec![];
+        for (meta, t) in transitions {
+            let (function, id) = (t.function_name().to_string(), t.id().to_string());
+            let savepoint = txn.savepoint();
+            let queued = execs.len();
+            let result = match Function::from_str(&function) {
+                Ok(Function::Vote) => self.handle_vote(txn, meta, t, &mut execs),
+                Ok(Function::MoveToNext) => self.handle_move(txn, meta, t),
+                Ok(Function::OpenGame) => self.handle_open(txn, meta, t),
+                Err(_) => Ok(()),
+            };
+            if let Err(e) = result {
+                tracing::error!("handle {function} transition {id} error: {:?}", e);
+                if e.is_transient() {
+                    return Err(e);
                 }
-                Execution::OpenGame => {
-                    let node_id = self.open_game_remote()?.node_id;
-                    let inputs = vec![format!("{}u128", node_id)];
-                    ("open_game", inputs)
+                txn.rollback_to(savepoint);
+                execs.truncate(queued);
+                self.reject(txn, &function, id, &e)?;
+            }
+        }
+        Ok(execs)
+    }
+
+    /// Starts a transaction over the tables of Mori.
+    pub fn transaction(&self) -> DBTransaction {
+        self.mori_nodes.transaction()
+    }
+
+    pub fn execute_program(self, mut rx: Receiver<Execution>) -> Result<()> {
+        let mut senders = Vec::with_capacity(self.workers);
+        for worker in 0..self.workers {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            senders.push(sender);
+
+            let self_clone = self.clone();
+            std::thread::spawn(move || self_clone.execution_worker(worker, receiver));
+        }
+        let pool = WorkerPool::new(senders);
+        tracing::info!("execution pool started with {} workers", pool.size());
+
+        while let Some(exec) = rx.blocking_recv() {
+            let game_id = match self.execution_game(&exec) {
+                Ok(game_id) => game_id,
+                Err(e) => {
+                    tracing::error!("execution {exec:?} game lookup error: {:?}", e);
+                    None
                 }
             };
+            pool.dispatch(game_id, exec)?;
+        }
 
-            let result = self.pm.execute_program(
-                ALEO_CONTRACT.get().unwrap(),
-                function,
-                inputs.iter(),
-                FEE_NUM,
-                None,
-                None,
-            );
+        Err(MoriError::execution("mori move channel closed"))
+    }
 
-            result
-        };
+    fn execution_worker(&self, worker: usize, rx: std::sync::mpsc::Receiver<Execution>) {
+        while let Ok(exec) = rx.recv() {
+            if self.fees.available() == 0 {
+                if let Err(e) = self.fees.refill(&self.aleo_client, &self.pk, &self.vk) {
+                    tracing::warn!("worker {worker} refill fee records error: {:?}", e);
+                }
+            }
 
-        while let Some(exec) = rx.blocking_recv() {
-            match handler(exec.clone()) {
-                Ok(resp) => tracing::info!("execution result: {:?}", resp),
-                Err(e) => tracing::error!("execution {exec:?} error: {:?}", e),
+            let lease = self.fees.acquire();
+            let result = match self.execution_inputs(exec.clone()) {
+                Ok((function, inputs)) => self.submit(function, inputs, lease),
+                Err(e) => {
+                    // nothing was submitted, the record can pay for the next one
+                    if let Some(lease) = lease {
+                        self.fees.release(lease);
+                    }
+                    Err(e)
+                }
+            };
+            match result {
+                Ok(resp) => {
+                    tracing::info!("worker {worker} execution result: {:?}", resp);
+                }
+                Err(e) => {
+                    tracing::error!("worker {worker} execution {exec:?} error: {:?}", e);
+                    let mut txn = self.transaction();
+                    let stored = self
+                        .reject(&mut txn, "execution", exec.id(), &e)
+                        .and_then(|_| txn.commit());
+                    if let Err(e) = stored {
+                        tracing::error!("store rejection error: {:?}", e);
+                    }
+                }
             }
         }
+        tracing::warn!("worker {worker} channel closed");
+    }
 
-        anyhow::bail!("mori move channel closed")
+    /// The game an execution belongs to, used to keep the moves of one game in order.
+    fn execution_game(&self, exec: &Execution) -> Result<Option<u128>> {
+        match exec {
+            Execution::MoveToNext(mov) => match mov.parent_id {
+                Some(parent_id) => Ok(Some(self.game_root(&mut self.transaction(), parent_id)?)),
+                None => Ok(None),
+            },
+            Execution::OpenGame(_) => Ok(None),
+        }
+    }
+
+    pub fn game_root(&self, txn: &mut DBTransaction, node_id: u128) -> Result<u128> {
+        let mut cur = node_id;
+        while let Some(node) = txn.get(&self.mori_nodes, &cur)? {
+            if node.is_root() {
+                break;
+            }
+            cur = node.from.node_id;
+        }
+        Ok(cur)
+    }
+
+    /// The program function and inputs of `exec`, computed before any fee
+    /// record is used.
+    fn execution_inputs(&self, exec: Execution) -> Result<(Function, Vec<String>)> {
+        tracing::warn!("received execution: {:?}", exec);
+        match exec {
+            Execution::MoveToNext(mov) => {
+                let game_state = GameState::from_vec_i8(&mov.state)?;
+                game_state.check_status(mov.game_status)?;
+                let parent_id = mov.parent_id.ok_or(MoriError::validation("no parent id"))?;
+                let human_move = mov
+                    .human_move
+                    .ok_or(MoriError::validation("no human mov"))?;
+                let inputs = MoveToNextInputs {
+                    parent_id,
+                    node_id: mov.node_id,
+                    state: game_state.raw(),
+                    game_status: mov.game_status,
+                    mov: human_move,
+                };
+                Ok((MoveToNextInputs::FUNCTION, inputs.to_inputs()))
+            }
+            Execution::OpenGame(request) => {
+                let node_id = self.open_game_remote()?.node_id;
+                if let Some(request) = request {
+                    // lets the scheduler tell its games from the others
+                    self.scheduled_opens.update(&request, |open| {
+                        if let Some(open) = open {
+                            open.node_id = Some(node_id);
+                        }
+                    })?;
+                }
+                let inputs = OpenGameInputs { node_id };
+                Ok((OpenGameInputs::FUNCTION, inputs.to_inputs()))
+            }
+        }
+    }
+
+    /// Proves and broadcasts one execution. The fee record is consumed even if
+    /// this fails, the transaction may have reached the network anyway.
+    fn submit(
+        &self,
+        function: Function,
+        inputs: Vec<String>,
+        lease: Option<FeeLease<N>>,
+    ) -> Result<String> {
+        self.pm
+            .execute_program(
+                ALEO_CONTRACT.get().unwrap(),
+                function.name(),
+                inputs.iter(),
+                FEE_NUM,
+                lease.map(|l| l.record),
+                None,
+            )
+            .map_err(MoriError::execution)
     }
 
     pub fn initial(self, rx: Receiver<Execution>) -> Self {
@@ -182,113 +480,256 @@ impl<N: Network> Mori<N> {
             std::thread::sleep(std::time::Duration::from_secs(15));
         });
 
+        let self_clone = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(std::time::Duration::from_secs(RECONCILE_INTERVAL_SECS));
+            if let Err(e) = self_clone.reconcile_nodes() {
+                tracing::error!("reconcile nodes error: {:?}", e);
+            }
+        });
+
         self
     }
 
-    pub fn handle_vote(&self, t: Transition<N>) -> anyhow::Result<()> {
+    /// Counts the votes of `t`, the moves they decide are pushed to `execs`.
+    pub fn handle_vote(
+        &self,
+        txn: &mut DBTransaction,
+        meta: BlockMeta,
+        t: Transition<N>,
+        execs: &mut Vec<Execution>,
+    ) -> Result<()> {
         tracing::info!("Got a vote from {}", t.id());
-        if let Some(output) = t.outputs().iter().next() {
-            if let Some(record) = output.record() {
-                if record.1.is_owner(&self.vk) {
-                    let (_, record) = record;
-                    let record = record.decrypt(&self.vk)?;
-                    tracing::info!("Got a vote record {}", record);
-                    let vote = Vote::try_from_record(record)?;
-
-                    let node = self.mori_nodes.get(&vote.node_id)?;
-                    if let Some(node) = node {
-                        let node_id = node.node_id;
-                        let mut node = node;
-
-                        if node.check_and_add_vote(vote) {
-                            let movs = self.move_to_next_remote(node.clone())?;
-                            for mov in movs {
-                                self.tx.blocking_send(Execution::MoveToNext(mov))?;
-                            }
-                        }
-                        self.mori_nodes.insert(&node_id, &node)?;
-                    }
+        let transition_id = t.id().to_string();
+
+        for (index, output) in t.outputs().iter().enumerate() {
+            let index = index as u8;
+            let (vote, commitment) = match self.decode_vote_output(output) {
+                Ok(Some(vote)) => vote,
+                Ok(None) => continue,
+                Err((reason, detail)) => {
+                    self.skip_vote(txn, meta, &transition_id, index, reason, detail)?;
+                    continue;
+                }
+            };
+
+            // a replayed block must not count its votes twice
+            let key = (vote.node_id, transition_id.clone(), index);
+            if txn.contain(&self.votes, &key)? {
+                continue;
+            }
+            let vote_record = VoteRecord {
+                vote: vote.clone(),
+                transition_id: transition_id.clone(),
+                block_height: meta.height,
+                block_timestamp: meta.timestamp,
+                output_index: index,
+                commitment,
+            };
+            txn.insert(&self.votes, &key, &vote_record)?;
+
+            let Some(mut node) = txn.get(&self.mori_nodes, &vote.node_id)? else {
+                if txn.contain(&self.finished_games, &vote.node_id)? {
+                    let detail = format!("node {} is archived", vote.node_id);
+                    self.skip_vote(
+                        txn,
+                        meta,
+                        &transition_id,
+                        index,
+                        VoteSkipReason::GameOver,
+                        detail,
+                    )?;
+                    continue;
                 }
+                let detail = format!("node {} is not indexed", vote.node_id);
+                self.skip_vote(
+                    txn,
+                    meta,
+                    &transition_id,
+                    index,
+                    VoteSkipReason::UnknownNode,
+                    detail,
+                )?;
+                continue;
+            };
+
+            if let Err(reason) = node.check_and_add_vote(vote.clone()) {
+                let detail = format!("vote {} on node {}", vote.mov, node.node_id);
+                self.skip_vote(txn, meta, &transition_id, index, reason, detail)?;
+                continue;
             }
+            self.credit_vote(txn, &vote.sender, vote.node_id)?;
+
+            let movs = self.move_to_next_remote(node.clone())?;
+            execs.extend(movs.into_iter().map(Execution::MoveToNext));
+            txn.insert(&self.mori_nodes, &node.node_id, &node)?;
         }
         Ok(())
     }
 
-    pub fn handle_open(&self, t: Transition<N>) -> anyhow::Result<()> {
-        let input = t.inputs()[0].clone();
+    /// Decodes a vote from a private record addressed to us or from a public `Vote` struct.
+    /// Outputs that do not carry a vote are ignored.
+    fn decode_vote_output(
+        &self,
+        output: &Output<N>,
+    ) -> Result<Option<(Vote, String)>, (VoteSkipReason, String)> {
+        let undecodable = |e: MoriError| (VoteSkipReason::Undecodable, e.to_string());
+
+        if let Some((commitment, record)) = output.record() {
+            if !record.is_owner(&self.vk) {
+                return Err((VoteSkipReason::NotOwner, commitment.to_string()));
+            }
+            let record = record
+                .decrypt(&self.vk)
+                .map_err(|e| undecodable(MoriError::decode(e)))?;
+            tracing::info!("Got a vote record {}", record);
+            let vote = Vote::from_record(&record).map_err(undecodable)?;
+            return Ok(Some((vote, commitment.to_string())));
+        }
 
-        if let Input::Public(_, Some(p)) = input {
-            let node_id = handle_u128_plaintext(&p)?;
-            let node = self.get_remote_node(node_id)?;
-            tracing::info!(
-                "Got a new open game id {node_id} node:\n {}",
-                node.state.pretty()
-            );
-            self.mori_nodes.insert(&node_id, &node)?;
+        if let Output::Public(id, Some(p @ Plaintext::Struct(..))) = output {
+            tracing::info!("Got a public vote {}", p);
+            let vote = Vote::from_plaintext(p).map_err(undecodable)?;
+            return Ok(Some((vote, id.to_string())));
         }
 
-        Ok(())
+        Ok(None)
     }
 
-    pub fn handle_move(&self, t: Transition<N>) -> anyhow::Result<()> {
-        let inputs = t.inputs();
+    fn skip_vote(
+        &self,
+        txn: &mut DBTransaction,
+        meta: BlockMeta,
+        transition_id: &str,
+        output_index: u8,
+        reason: VoteSkipReason,
+        detail: String,
+    ) -> Result<()> {
+        tracing::warn!("skip vote {transition_id}/{output_index}: {reason} ({detail})");
+        let skipped = SkippedVote {
+            transition_id: transition_id.to_string(),
+            output_index,
+            block_height: meta.height,
+            reason,
+            detail,
+        };
+        txn.insert(
+            &self.skipped_votes,
+            &(transition_id.to_string(), output_index),
+            &skipped,
+        )
+    }
 
-        let node_id = inputs[1].clone();
-        if let Input::Public(_, Some(p)) = node_id {
-            let node_id = handle_u128_plaintext(&p)?;
-            let node = self.get_remote_node(node_id)?;
-            tracing::info!(
-                "Got a new move id {node_id} node:\n {}",
-                node.state.pretty()
-            );
-            self.mori_nodes.insert(&node_id, &node)?;
+    pub fn handle_open(
+        &self,
+        txn: &mut DBTransaction,
+        meta: BlockMeta,
+        t: Transition<N>,
+    ) -> Result<()> {
+        let inputs = decode_transition(&t, &self.vk, OpenGameInputs::from_values)?;
+        let node = open_game_effect(&inputs);
+        self.mirror_node(txn, &node, meta.height, MirrorSource::Finalize)?;
+        let node = self.analyse_node(txn, node)?;
+        let node_id = node.node_id;
+        tracing::info!(
+            "Got a new open game id {node_id} node:\n {}",
+            node.state.pretty()
+        );
+        txn.insert(&self.mori_nodes, &node_id, &node)?;
+        txn.insert(&self.node_births, &node_id, &meta)?;
+        self.index_position(txn, &node)?;
+        if node.game_status != 0 {
+            self.finalize_game(txn, &node, None)?;
         }
 
         Ok(())
     }
 
-    pub fn get_remote_node(&self, node_id: u128) -> anyhow::Result<GameNode> {
-        let value = self.aleo_client.get_mapping_value(
-            ALEO_CONTRACT.get().unwrap(),
-            "nodes",
-            Plaintext::from_str(&format!("{}u128", node_id))?,
-        )?;
-
-        let ai_path = format!("{}/api/nodes/{}", self.ai_dest, node_id);
-        let ai_resp = ureq::get(&ai_path)
-            .set("Authorization", &self.ai_token)
-            .call()?
-            .into_json::<RestResponse>()?;
-
-        if let aleo_rust::Value::Plaintext(p) = value {
-            let mut node = GameNode::from_plaintext(&p)?;
-            node.update_valid_movs(ai_resp.valid_moves);
-            Ok(node)
-        } else {
-            anyhow::bail!("invalid node value")
+    pub fn handle_move(
+        &self,
+        txn: &mut DBTransaction,
+        meta: BlockMeta,
+        t: Transition<N>,
+    ) -> Result<()> {
+        let inputs = decode_transition(&t, &self.vk, MoveToNextInputs::from_values)?;
+        let node = move_to_next_effect(&inputs)?;
+        self.mirror_node(txn, &node, meta.height, MirrorSource::Finalize)?;
+        let node = self.analyse_node(txn, node)?;
+        let node_id = node.node_id;
+        tracing::info!(
+            "Got a new move id {node_id} node:\n {}",
+            node.state.pretty()
+        );
+        txn.insert(&self.mori_nodes, &node_id, &node)?;
+        txn.insert(&self.node_births, &node_id, &meta)?;
+        self.index_position(txn, &node)?;
+        self.credit_majority(txn, &node)?;
+        if node.game_status != 0 {
+            self.finalize_game(txn, &node, None)?;
         }
+
+        Ok(())
     }
 
-    pub fn open_game_remote(&self) -> anyhow::Result<RestResponse> {
+    /// Reads a node from the chain mapping as of `height` and analyses it.
+    pub fn get_remote_node(
+        &self,
+        txn: &mut DBTransaction,
+        node_id: u128,
+        height: u32,
+    ) -> Result<GameNode> {
+        let (_, node) = self
+            .fetch_chain_nodes(txn, &[node_id], height)?
+            .pop()
+            .ok_or(MoriError::NotFound(format!("node {node_id}")))?;
+        self.analyse_node(txn, node?)
+    }
+
+    /// Fills in the valid moves of a node, from an analysed transposition or the AI.
+    pub fn analyse_node(&self, txn: &mut DBTransaction, mut node: GameNode) -> Result<GameNode> {
+        let node_id = node.node_id;
+        let valid_movs = match self.transposed_valid_movs(txn, &node)? {
+            Some(movs) => movs,
+            None => {
+                let ai_path = format!("{}/api/nodes/{}", self.ai_dest, node_id);
+                ureq::get(&ai_path)
+                    .set("Authorization", &self.ai_token)
+                    .call()?
+                    .into_json::<RestResponse>()
+                    .map_err(MoriError::ai)?
+                    .valid_moves
+            }
+        };
+        node.update_valid_movs(valid_movs);
+        Ok(node)
+    }
+
+    pub fn open_game_remote(&self) -> Result<RestResponse> {
         let dest = format!("{}/api/nodes", self.ai_dest);
         let node_resp = ureq::post(&dest)
             .set("Authorization", &self.ai_token)
             .call()?
-            .into_json()?;
+            .into_json()
+            .map_err(MoriError::ai)?;
         tracing::info!("open game remote resp {:?}", node_resp);
         Ok(node_resp)
     }
 
-    pub fn move_to_next_remote(&self, node: GameNode) -> anyhow::Result<Vec<RestResponse>> {
+    pub fn move_to_next_remote(&self, node: GameNode) -> Result<Vec<RestResponse>> {
+        self.request_moves(MovRequest::from_node(node))
+    }
+
+    /// Asks the AI to play the voters' move of a request and to answer it.
+    pub fn request_moves(&self, req: MovRequest) -> Result<Vec<RestResponse>> {
         let dest = format!("{}/api/nodes", self.ai_dest);
-        let req = MovRequest::from_node(node);
 
         tracing::info!("move to next req {}", ureq::json!(req));
 
         let resp: Vec<RestResponse> = ureq::post(&dest)
             .set("Authorization", &self.ai_token)
             .send_json(ureq::json!(req))?
-            .into_json()?;
+            .into_json()
+            .map_err(MoriError::ai)?;
         tracing::info!("move to next resp {:?}", resp);
 
         // TODO: mov = 64
@@ -315,22 +756,203 @@ impl<N: Network> Mori<N> {
         Ok(resp)
     }
 
-    pub fn get_all_nodes(&self) -> anyhow::Result<Vec<(u128, GameNode)>> {
+    pub fn get_all_nodes(&self) -> Result<Vec<(u128, GameNode)>> {
         let nodes = self.mori_nodes.get_all()?;
         Ok(nodes)
     }
 
-    pub fn set_cur_height(&self, height: u32) -> anyhow::Result<()> {
-        let cur = self.network_height.get(&self.network_key)?.unwrap_or(0);
-        if height > cur {
-            self.network_height.insert(&self.network_key, &height)?;
-        }
-        Ok(())
+    pub fn nodes_by_status(&self, game_status: i8) -> Result<Vec<(u128, GameNode)>> {
+        self.mori_nodes.query(&self.nodes_by_status, &game_status)
+    }
+
+    pub fn children(&self, node_id: u128) -> Result<Vec<(u128, GameNode)>> {
+        self.mori_nodes.query(&self.children, &node_id)
+    }
+
+    pub fn votes_by_node(&self, node_id: u128) -> Result<Vec<VoteRecord>> {
+        let mut votes = self
+            .votes
+            .scan_prefix(&node_id)
+            .map(|entry| entry.map(|(_, v)| v))
+            .collect::<Result<Vec<_>>>()?;
+        votes.sort_by_key(|v| (v.block_height, v.transition_id.clone()));
+        Ok(votes)
+    }
+
+    pub fn votes_by_address(&self, address: &str) -> Result<Vec<VoteRecord>> {
+        let mut votes = self
+            .votes
+            .query(&self.votes_by_sender, &address.to_string())?
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>();
+        votes.sort_by_key(|v| (v.block_height, v.transition_id.clone()));
+        Ok(votes)
+    }
+
+    /// Votes included in blocks `from..to`.
+    pub fn votes_by_height(&self, from: u32, to: u32) -> Result<Vec<VoteRecord>> {
+        let mut votes = self
+            .votes
+            .query_range(&self.votes_by_height, &from, &to)?
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>();
+        votes.sort_by_key(|v| (v.block_height, v.transition_id.clone()));
+        Ok(votes)
+    }
+
+    /// Votes skipped in blocks `from..to`, by height then transition.
+    pub fn skipped_votes(&self, from: u32, to: u32) -> Result<Vec<SkippedVote>> {
+        Ok(self
+            .skipped_votes
+            .query_range(&self.skipped_by_height, &from, &to)?
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect())
+    }
+
+    fn reject(
+        &self,
+        txn: &mut DBTransaction,
+        source: &str,
+        id: String,
+        e: &MoriError,
+    ) -> Result<()> {
+        let rejection = Rejection {
+            source: source.to_string(),
+            id: id.clone(),
+            reason: e.to_string(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        };
+        txn.insert(&self.rejections, &format!("{source}/{id}"), &rejection)
+    }
+
+    pub fn get_rejections(&self) -> Result<Vec<Rejection>> {
+        let mut rejections = self
+            .rejections
+            .get_all()?
+            .into_iter()
+            .map(|(_, r)| r)
+            .collect::<Vec<_>>();
+        rejections.sort_by_key(|r| r.timestamp);
+        Ok(rejections)
+    }
+
+    /// Skips the blocks below `height` when looking for fee records.
+    pub fn scan_fees_from(&self, height: u32) {
+        self.fees.scan_from(height);
+    }
+
+    /// Whether sync caught up with the chain since startup.
+    pub fn is_synced(&self) -> bool {
+        self.synced.load(Ordering::Acquire)
+    }
+
+    /// The height sync has reached.
+    pub fn synced_height(&self) -> Result<u32> {
+        Ok(self.network_height.get(&self.network_key)?.unwrap_or(0))
+    }
+
+    pub fn set_cur_height(&self, height: u32) -> Result<()> {
+        self.network_height.update(&self.network_key, |cur| {
+            if cur.unwrap_or(0) < height {
+                *cur = Some(height);
+            }
+        })
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Execution {
     MoveToNext(RestResponse),
-    OpenGame,
+    /// Carries the id of the request when the scheduler asked for it.
+    OpenGame(Option<u128>),
+}
+
+impl Execution {
+    pub fn id(&self) -> String {
+        match self {
+            Execution::MoveToNext(mov) => format!("move_to_next/{}", mov.node_id),
+            Execution::OpenGame(_) => format!(
+                "open_game/{}",
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_millis())
+                    .unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// An open node at the empty board, reached from `parent` by move 19.
+#[cfg(test)]
+pub(crate) fn test_node(node_id: u128, parent: u128) -> GameNode {
+    GameNode {
+        node_id,
+        state: cores::GameState::zero(),
+        from: cores::NodeEdge {
+            node_id: parent,
+            mov: 19,
+        },
+        game_status: 0,
+        valid_movs: vec![19],
+        votes: vec![],
+    }
+}
+
+#[test]
+fn test_vote_queries() {
+    let (mori, _rx) = Mori::<aleo_rust::Testnet3>::test_open().unwrap();
+    let record = |node_id, sender: &str, block_height| VoteRecord {
+        vote: Vote {
+            sender: sender.into(),
+            node_id,
+            mov: 19,
+        },
+        transition_id: format!("at1transition{node_id}{sender}"),
+        block_height,
+        block_timestamp: block_height as i64 * 10,
+        output_index: 0,
+        commitment: String::new(),
+    };
+    for vote in [
+        record(2, "alice", 7),
+        record(1, "bob", 5),
+        record(1, "alice", 3),
+        record(10, "bob", 9),
+    ] {
+        let key = (vote.vote.node_id, vote.transition_id.clone(), 0);
+        mori.votes.insert(&key, &vote).unwrap();
+    }
+
+    let heights = |votes: Vec<VoteRecord>| votes.iter().map(|v| v.block_height).collect::<Vec<_>>();
+    // node 1 does not pick up the votes of node 10
+    assert_eq!(heights(mori.votes_by_node(1).unwrap()), vec![3, 5]);
+    assert_eq!(heights(mori.votes_by_address("bob").unwrap()), vec![5, 9]);
+    assert_eq!(heights(mori.votes_by_height(5, 9).unwrap()), vec![5, 7]);
+    assert!(mori.votes_by_address("carol").unwrap().is_empty());
+
+    for (transition_id, block_height) in [("at1b", 8), ("at1a", 8), ("at1c", 2)] {
+        let skipped = SkippedVote {
+            transition_id: transition_id.to_string(),
+            output_index: 0,
+            block_height,
+            reason: VoteSkipReason::NotOwner,
+            detail: String::new(),
+        };
+        let key = (skipped.transition_id.clone(), 0);
+        mori.skipped_votes.insert(&key, &skipped).unwrap();
+    }
+    let skipped = mori.skipped_votes(3, u32::MAX).unwrap();
+    assert_eq!(
+        skipped
+            .iter()
+            .map(|v| v.transition_id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["at1a", "at1b"]
+    );
 }
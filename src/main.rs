@@ -1,14 +1,35 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use aleo_rust::{Network, PrivateKey, Testnet3};
-use axum::extract::State;
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::Json;
 use backend::Execution;
-use backend::{cores::GameNode, Mori};
-use clap::Parser;
+use backend::{
+    backup::{self, BackupConfig},
+    cores::{GameNode, Rejection, SkippedVote, VoteRecord},
+    db::{Database, DB_PATH},
+    error::{MoriError, Result},
+    fsck::{FsckOptions, FsckReport},
+    games::FinishedGame,
+    notation::{self, ExportFormat},
+    players::PlayerStats,
+    program,
+    prune::{PruneMetrics, PruneOptions, PruneReport},
+    render::BoardView,
+    scheduler::{CronSchedule, SchedulerPolicy, SchedulerState},
+    store::StorageKind,
+    symmetry::Transposition,
+    timers::{TimeoutFallback, TurnDeadline, TurnTimerPolicy},
+    Mori,
+};
+use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 use tower_http::cors::{Any, CorsLayer};
 #[derive(Debug, Parser)]
@@ -29,11 +50,140 @@ pub struct Cli {
     #[clap(long)]
     pub port: u16,
 
+    /// Bearer token required by the `/admin/*` routes, they are not served
+    /// without one.
+    #[clap(long)]
+    pub admin_token: Option<String>,
+
     #[clap(long, default_value = "0")]
     pub from_height: u32,
 
-    #[clap(long, default_value = "mori.aleo")]
+    /// First block that can hold fee records of the key, `--from-height` by
+    /// default.
+    #[clap(long)]
+    pub fee_scan_from: Option<u32>,
+
+    #[clap(long, default_value = program::PROGRAM_ID)]
     pub program_name: String,
+
+    /// Where the indexed state is kept, `rocksdb` or `memory`. The memory
+    /// storage is lost on exit and cannot be backed up, it is meant for demos.
+    #[clap(long, default_value = "rocksdb")]
+    pub storage: StorageKind,
+
+    /// Number of workers proving and submitting executions in parallel.
+    #[clap(long, default_value = "4")]
+    pub workers: usize,
+
+    /// Check the stored nodes, print the report and exit.
+    #[clap(long)]
+    pub fsck: bool,
+
+    /// With `--fsck`, also compare the nodes against the chain mapping.
+    #[clap(long, requires = "fsck")]
+    pub fsck_chain: bool,
+
+    /// With `--fsck`, re-fetch broken nodes from the chain and the AI.
+    #[clap(long, requires = "fsck")]
+    pub repair: bool,
+
+    /// Directory the database backups are written to.
+    #[clap(long, default_value = "./mori_backups")]
+    pub backup_dir: PathBuf,
+
+    /// Seconds between two database backups, 0 disables them.
+    #[clap(long, default_value = "3600")]
+    pub backup_interval: u64,
+
+    /// Number of database backups kept in `--backup-dir`.
+    #[clap(long, default_value = "24")]
+    pub backup_keep: usize,
+
+    /// Seconds between two prune runs, 0 disables them.
+    #[clap(long, default_value = "0")]
+    pub prune_interval: u64,
+
+    /// Prune the open leaves nobody voted on for this many blocks.
+    #[clap(long)]
+    pub prune_leaf_blocks: Option<u32>,
+
+    /// Prune the open leaves nobody voted on for this many days.
+    #[clap(long)]
+    pub prune_leaf_days: Option<u64>,
+
+    /// Only log what the scheduled prune runs would remove.
+    #[clap(long)]
+    pub prune_dry_run: bool,
+
+    /// Number of active games the scheduler keeps open, 0 disables it unless
+    /// `--game-schedule` is set.
+    #[clap(long, default_value = "0")]
+    pub target_games: usize,
+
+    /// A game nobody voted on for this many seconds no longer counts as active.
+    #[clap(long)]
+    pub game_idle_secs: Option<u64>,
+
+    /// Cron-like UTC schedule opening one more game, e.g. `@daily` or
+    /// `0 18 * * 1-5`. Can be repeated.
+    #[clap(long)]
+    pub game_schedule: Vec<CronSchedule>,
+
+    /// Do not open games while the fee records hold fewer microcredits.
+    #[clap(long, default_value = "0")]
+    pub min_fee_balance: u64,
+
+    /// Seconds between two checks of the game scheduler.
+    #[clap(long, default_value = "60")]
+    pub scheduler_interval: u64,
+
+    /// Blocks the voters have on a node before the timeout fallback is played.
+    #[clap(long)]
+    pub turn_deadline_blocks: Option<u32>,
+
+    /// Seconds the voters have on a node before the timeout fallback is played.
+    #[clap(long)]
+    pub turn_deadline_secs: Option<u64>,
+
+    /// Played when a turn times out: `random`, `best_square` or `pass`.
+    /// `pass` forfeits the game when the voters have a legal move.
+    #[clap(long, default_value = "best_square")]
+    pub turn_fallback: TimeoutFallback,
+
+    /// Seconds between two checks of the turn deadlines.
+    #[clap(long, default_value = "30")]
+    pub turn_timer_interval: u64,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Back up the database to `--backup-dir` and exit.
+    Backup,
+
+    /// Replace the database by a backup and exit, the server must be stopped.
+    Restore {
+        /// Backup to restore, the newest one in `--backup-dir` by default.
+        #[clap(long)]
+        from: Option<PathBuf>,
+    },
+
+    /// Dump every table to a JSON-lines file and exit.
+    Export {
+        #[clap(long)]
+        out: PathBuf,
+    },
+
+    /// Load a JSON-lines dump written by `export` and exit.
+    Import {
+        #[clap(long)]
+        input: PathBuf,
+    },
+
+    /// Compare `program/mori.aleo` with the deployed program and exit.
+    VerifyProgram,
 }
 
 #[tokio::main]
@@ -47,19 +197,147 @@ async fn main() {
         aleo_rpc,
         pk,
         port,
+        admin_token,
         from_height,
+        fee_scan_from,
         program_name,
+        storage,
+        workers,
+        fsck,
+        fsck_chain,
+        repair,
+        backup_dir,
+        backup_interval,
+        backup_keep,
+        prune_interval,
+        prune_leaf_blocks,
+        prune_leaf_days,
+        prune_dry_run,
+        target_games,
+        game_idle_secs,
+        game_schedule,
+        min_fee_balance,
+        scheduler_interval,
+        turn_deadline_blocks,
+        turn_deadline_secs,
+        turn_fallback,
+        turn_timer_interval,
+        command,
     } = cli;
+    let backups = BackupConfig {
+        dir: backup_dir,
+        interval_secs: backup_interval,
+        keep: backup_keep,
+    };
+
+    // the database must not be open while it is replaced
+    if let Some(Command::Restore { from }) = &command {
+        let from = match from {
+            Some(from) => from.clone(),
+            None => {
+                let newest = backup::list_backups(&backups.dir)
+                    .expect("Failed to list backups")
+                    .pop()
+                    .expect("No backup to restore");
+                newest.path
+            }
+        };
+        let replaced = backup::restore_backup(&from, std::path::Path::new(DB_PATH))
+            .expect("Failed to restore backup");
+        tracing::info!("restored the database from {}", from.display());
+        if let Some(replaced) = replaced {
+            tracing::info!("the replaced database was moved to {}", replaced.display());
+        }
+        return;
+    }
 
     // Init Mori Aleo
     let pk = PrivateKey::<Testnet3>::from_str(&pk).expect("Invalid private key");
     let (tx, rx) = tokio::sync::mpsc::channel(100);
-    let mori = Mori::new(aleo_rpc, pk, tx, program_name, ai_dest, ai_token)
-        .expect("Failed to initialize Mori");
+    let db = Database::open_storage(storage).expect("Failed to open storage");
+    let mori = Mori::new(
+        db,
+        aleo_rpc,
+        pk,
+        tx,
+        program_name.clone(),
+        ai_dest,
+        ai_token,
+        workers,
+    )
+    .expect("Failed to initialize Mori");
     // set from height
     mori.set_cur_height(from_height)
         .expect("Failed to set from height");
+    mori.scan_fees_from(fee_scan_from.unwrap_or(from_height));
+
+    if fsck {
+        let options = FsckOptions {
+            chain: fsck_chain,
+            repair,
+        };
+        let report = mori.fsck(options).expect("Failed to check nodes");
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("Failed to serialize report")
+        );
+        return;
+    }
+
+    let verify = matches!(command, Some(Command::VerifyProgram));
+    match command {
+        Some(Command::Backup) => {
+            let info = mori.backup(&backups).expect("Failed to back up");
+            tracing::info!("backed up the database to {}", info.path.display());
+            return;
+        }
+        Some(Command::Export { out }) => {
+            let count = mori.export_jsonl(&out).expect("Failed to export");
+            tracing::info!("exported {count} entries to {}", out.display());
+            return;
+        }
+        Some(Command::Import { input }) => {
+            let count = mori.import_jsonl(&input).expect("Failed to import");
+            tracing::info!("imported {count} entries from {}", input.display());
+            return;
+        }
+        Some(Command::VerifyProgram) | Some(Command::Restore { .. }) | None => {}
+    }
+
+    // the bindings decode every transition, they must match the chain
+    match program::matches_deployed(&mori.aleo_client, &program_name) {
+        Ok(true) => tracing::info!("program/mori.aleo matches the deployed {program_name}"),
+        Ok(false) if verify => {
+            panic!("program/mori.aleo does not match the deployed {program_name}")
+        }
+        Ok(false) => tracing::warn!("program/mori.aleo does not match the deployed {program_name}"),
+        Err(e) if verify => panic!("Failed to fetch the deployed {program_name}: {e}"),
+        Err(e) => tracing::warn!("could not verify the deployed {program_name}: {e}"),
+    }
+    if verify {
+        return;
+    }
     let mori = mori.initial(rx);
+    mori.schedule_backups(backups);
+    let prune = PruneOptions {
+        leaf_ttl_blocks: prune_leaf_blocks,
+        leaf_ttl_secs: prune_leaf_days.map(|days| days * 24 * 60 * 60),
+        dry_run: prune_dry_run,
+    };
+    mori.schedule_prune(prune, prune_interval);
+    mori.schedule_games(SchedulerPolicy {
+        target_games,
+        idle_secs: game_idle_secs,
+        schedules: game_schedule,
+        min_fee_balance,
+        interval_secs: scheduler_interval,
+    });
+    mori.schedule_turn_timers(TurnTimerPolicy {
+        deadline_blocks: turn_deadline_blocks,
+        deadline_secs: turn_deadline_secs,
+        fallback: turn_fallback,
+        interval_secs: turn_timer_interval,
+    });
 
     // Init Mori Rest
     let cors = CorsLayer::new()
@@ -69,13 +347,42 @@ async fn main() {
             axum::http::Method::POST,
             axum::http::Method::OPTIONS,
         ])
-        .allow_headers([axum::http::header::CONTENT_TYPE]);
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]);
 
-    let router = axum::Router::new()
+    let mut router = axum::Router::new()
         .route("/node/list", get(list_nodes))
+        .route("/node/:node_id/children", get(list_children))
         .route("/open_game", post(open_game))
-        .with_state(mori)
-        .layer(cors);
+        .route("/scheduler", get(get_scheduler))
+        .route("/rejections", get(list_rejections))
+        .route("/votes", get(list_votes_by_height))
+        .route("/votes/skipped", get(list_skipped_votes))
+        .route("/votes/node/:node_id", get(list_votes_by_node))
+        .route("/votes/address/:address", get(list_votes_by_address))
+        .route("/players/:address", get(get_player))
+        .route("/leaderboard", get(get_leaderboard))
+        .route("/games/finished", get(list_finished_games))
+        .route("/games/finished/:leaf_id", get(get_finished_game))
+        .route("/game/:node_id/export", get(export_game))
+        .route("/game/import", post(import_game))
+        .route("/node/:node_id/board.svg", get(board_svg))
+        .route("/node/:node_id/board.png", get(board_png))
+        .route("/node/:node_id/transpositions", get(list_transpositions));
+    match admin_token {
+        Some(token) => {
+            let admin = axum::Router::new()
+                .route("/fsck", post(run_fsck))
+                .route("/prune", post(run_prune))
+                .route("/prune/metrics", get(get_prune_metrics))
+                .route_layer(middleware::from_fn_with_state(
+                    Arc::<str>::from(token),
+                    require_admin,
+                ));
+            router = router.nest("/admin", admin);
+        }
+        None => tracing::warn!("no --admin-token, the /admin routes are disabled"),
+    }
+    let router = router.with_state(mori).layer(cors);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     let listener = tokio::net::TcpListener::bind(addr)
@@ -88,37 +395,266 @@ async fn main() {
 
 async fn list_nodes<N: Network>(
     State(mori): State<Mori<N>>,
-) -> anyhow::Result<Json<NodesResponse>, (StatusCode, String)> {
-    let nodes = match mori.get_all_nodes() {
-        Ok(nodes) => nodes,
-        Err(e) => {
-            tracing::error!("Failed to get all nodes: {}", e);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to get all nodes: {}", e),
-            ));
-        }
+    Query(query): Query<NodesQuery>,
+) -> Result<Json<NodesResponse>> {
+    let nodes = match query.status {
+        Some(status) => mori.nodes_by_status(status)?,
+        None => mori.get_all_nodes()?,
     };
-    let nodes = NodesResponse { nodes };
+    let deadlines = mori.turn_deadlines(&nodes)?;
+    let nodes = NodesResponse { nodes, deadlines };
 
     Ok(Json(nodes))
 }
 
-async fn open_game<N: Network>(
+async fn list_children<N: Network>(
     State(mori): State<Mori<N>>,
-) -> anyhow::Result<String, (StatusCode, String)> {
-    let exec = Execution::OpenGame;
-    mori.tx.send(exec).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to send execution: {}", e),
-        )
-    })?;
+    Path(node_id): Path<u128>,
+) -> Result<Json<NodesResponse>> {
+    let nodes = mori.children(node_id)?;
+    let deadlines = mori.turn_deadlines(&nodes)?;
+    Ok(Json(NodesResponse { nodes, deadlines }))
+}
+
+async fn open_game<N: Network>(State(mori): State<Mori<N>>) -> Result<String> {
+    let exec = Execution::OpenGame(None);
+    mori.tx
+        .send(exec)
+        .await
+        .map_err(|e| MoriError::execution(format!("Failed to send execution: {}", e)))?;
 
     Ok("alreay add in execution pipeline".to_string())
 }
 
+async fn get_scheduler<N: Network>(State(mori): State<Mori<N>>) -> Json<SchedulerState> {
+    Json(mori.scheduler_state())
+}
+
+async fn list_rejections<N: Network>(
+    State(mori): State<Mori<N>>,
+) -> Result<Json<RejectionsResponse>> {
+    let rejections = mori.get_rejections()?;
+    Ok(Json(RejectionsResponse { rejections }))
+}
+
+async fn list_votes_by_node<N: Network>(
+    State(mori): State<Mori<N>>,
+    Path(node_id): Path<u128>,
+) -> Result<Json<VotesResponse>> {
+    let votes = mori.votes_by_node(node_id)?;
+    Ok(Json(VotesResponse { votes }))
+}
+
+async fn list_votes_by_address<N: Network>(
+    State(mori): State<Mori<N>>,
+    Path(address): Path<String>,
+) -> Result<Json<VotesResponse>> {
+    let votes = mori.votes_by_address(&address)?;
+    Ok(Json(VotesResponse { votes }))
+}
+
+async fn list_votes_by_height<N: Network>(
+    State(mori): State<Mori<N>>,
+    Query(range): Query<HeightRange>,
+) -> Result<Json<VotesResponse>> {
+    let to = range.to.unwrap_or(u32::MAX);
+    let votes = mori.votes_by_height(range.from.unwrap_or(0), to)?;
+    Ok(Json(VotesResponse { votes }))
+}
+
+async fn list_skipped_votes<N: Network>(
+    State(mori): State<Mori<N>>,
+    Query(range): Query<HeightRange>,
+) -> Result<Json<SkippedVotesResponse>> {
+    let to = range.to.unwrap_or(u32::MAX);
+    let skipped = mori.skipped_votes(range.from.unwrap_or(0), to)?;
+    Ok(Json(SkippedVotesResponse { skipped }))
+}
+
+async fn get_player<N: Network>(
+    State(mori): State<Mori<N>>,
+    Path(address): Path<String>,
+) -> Result<Json<PlayerStats>> {
+    let player = mori
+        .get_player(&address)?
+        .ok_or(MoriError::NotFound(format!("player {}", address)))?;
+    Ok(Json(player))
+}
+
+async fn get_leaderboard<N: Network>(
+    State(mori): State<Mori<N>>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Json<LeaderboardResponse>> {
+    let players = mori.get_leaderboard(query.limit.unwrap_or(100))?;
+    Ok(Json(LeaderboardResponse { players }))
+}
+
+async fn list_finished_games<N: Network>(
+    State(mori): State<Mori<N>>,
+) -> Result<Json<FinishedGamesResponse>> {
+    let games = mori.get_finished_games()?;
+    Ok(Json(FinishedGamesResponse { games }))
+}
+
+async fn get_finished_game<N: Network>(
+    State(mori): State<Mori<N>>,
+    Path(leaf_id): Path<u128>,
+) -> Result<Json<FinishedGame>> {
+    let game = mori
+        .get_finished_game(leaf_id)?
+        .ok_or(MoriError::NotFound(format!("finished game {}", leaf_id)))?;
+    Ok(Json(game))
+}
+
+async fn export_game<N: Network>(
+    State(mori): State<Mori<N>>,
+    Path(node_id): Path<u128>,
+    Query(query): Query<ExportQuery>,
+) -> Result<String> {
+    let format = query.format.as_deref().unwrap_or("transcript");
+    let format = ExportFormat::from_str(format)?;
+    mori.export_game(node_id, format)?
+        .ok_or(MoriError::NotFound(format!("game node {}", node_id)))
+}
+
+async fn import_game(body: String) -> Result<Json<ImportResponse>> {
+    let movs = notation::transcript_from_text(&body)?;
+    let states = notation::replay(&movs)?
+        .iter()
+        .map(|s| s.to_vec_i8())
+        .collect();
+
+    Ok(Json(ImportResponse { states }))
+}
+
+async fn board_svg<N: Network>(
+    State(mori): State<Mori<N>>,
+    Path(node_id): Path<u128>,
+) -> Result<impl IntoResponse> {
+    let view = board_view(&mori, node_id)?;
+    Ok(([(header::CONTENT_TYPE, "image/svg+xml")], view.svg()))
+}
+
+async fn board_png<N: Network>(
+    State(mori): State<Mori<N>>,
+    Path(node_id): Path<u128>,
+) -> Result<impl IntoResponse> {
+    let view = board_view(&mori, node_id)?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], view.png()))
+}
+
+async fn list_transpositions<N: Network>(
+    State(mori): State<Mori<N>>,
+    Path(node_id): Path<u128>,
+) -> Result<Json<TranspositionsResponse>> {
+    let transpositions = mori
+        .get_transpositions(node_id)?
+        .ok_or(MoriError::NotFound(format!("node {}", node_id)))?;
+    Ok(Json(TranspositionsResponse { transpositions }))
+}
+
+/// Lets a request through only with `Authorization: Bearer <admin token>`.
+async fn require_admin(State(token): State<Arc<str>>, req: Request, next: Next) -> Response {
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if same_token(given.as_bytes(), token.as_bytes()) => next.run(req).await,
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+// compares every byte so the time taken does not tell how much matched
+fn same_token(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn run_fsck<N: Network>(
+    State(mori): State<Mori<N>>,
+    Query(options): Query<FsckOptions>,
+) -> Result<Json<FsckReport>> {
+    let report = mori.fsck(options)?;
+    Ok(Json(report))
+}
+
+async fn run_prune<N: Network>(
+    State(mori): State<Mori<N>>,
+    Query(options): Query<PruneOptions>,
+) -> Result<Json<PruneReport>> {
+    let report = mori.prune(options)?;
+    Ok(Json(report))
+}
+
+async fn get_prune_metrics<N: Network>(State(mori): State<Mori<N>>) -> Json<PruneMetrics> {
+    Json(mori.prune_metrics())
+}
+
+fn board_view<N: Network>(mori: &Mori<N>, node_id: u128) -> Result<BoardView> {
+    mori.board_view(node_id)?
+        .ok_or(MoriError::NotFound(format!("node {}", node_id)))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodesResponse {
     nodes: Vec<(u128, GameNode)>,
+    // deadlines of the listed nodes waiting for a vote
+    deadlines: Vec<TurnDeadline>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodesQuery {
+    status: Option<i8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejectionsResponse {
+    rejections: Vec<Rejection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VotesResponse {
+    votes: Vec<VoteRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedVotesResponse {
+    skipped: Vec<SkippedVote>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardResponse {
+    players: Vec<PlayerStats>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinishedGamesResponse {
+    games: Vec<FinishedGame>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranspositionsResponse {
+    transpositions: Vec<Transposition>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportQuery {
+    format: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportResponse {
+    states: Vec<Vec<i8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeightRange {
+    from: Option<u32>,
+    to: Option<u32>,
 }
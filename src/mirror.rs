@@ -0,0 +1,269 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use aleo_rust::{AleoAPIClient, Network, Plaintext};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cores::{GameNode, GameState, NodeEdge},
+    db::DBTransaction,
+    error::{MoriError, Result},
+    program::{self, MoveToNextInputs, OpenGameInputs},
+    utils::{FromAleoPlaintext, ToAleoInput},
+    Mori, ALEO_CONTRACT,
+};
+
+/// Mirrored reads older than this many blocks are read from the chain again.
+pub const MIRROR_TTL_BLOCKS: u32 = 720;
+
+/// Mapping reads in flight at once, the node API has no bulk mapping read.
+const FETCH_CONCURRENCY: usize = 8;
+
+/// Where a mirrored `nodes` entry comes from.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorSource {
+    /// Replayed from the finalize block of the transition that set it.
+    Finalize,
+    /// Read from the chain with `get_mapping_value`.
+    Rpc,
+}
+
+/// An entry of the on-chain `nodes` mapping as of `height`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MirroredNode {
+    pub node: GameNode,
+    pub height: u32,
+    pub source: MirrorSource,
+}
+
+/// A node of `mori_nodes` that disagrees with the chain mapping.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NodeMismatch {
+    pub node_id: u128,
+    pub local: GameNode,
+    pub chain: Option<GameNode>,
+    pub detail: String,
+}
+
+/// The `nodes` entry set by the finalize block of `open_game`.
+pub fn open_game_effect(inputs: &OpenGameInputs) -> GameNode {
+    GameNode {
+        node_id: inputs.node_id,
+        state: GameState::zero(),
+        from: NodeEdge { node_id: 0, mov: 0 },
+        game_status: 0,
+        valid_movs: vec![],
+        votes: vec![],
+    }
+}
+
+/// The `nodes` entry set by the finalize block of `move_to_next`.
+pub fn move_to_next_effect(inputs: &MoveToNextInputs) -> Result<GameNode> {
+    Ok(GameNode {
+        node_id: inputs.node_id,
+        state: GameState::try_from(inputs.state)?,
+        from: NodeEdge {
+            node_id: inputs.parent_id,
+            mov: inputs.mov,
+        },
+        game_status: inputs.game_status,
+        valid_movs: vec![],
+        votes: vec![],
+    })
+}
+
+fn read_chain_node<N: Network>(client: &AleoAPIClient<N>, node_id: u128) -> Result<GameNode> {
+    let value = client
+        .get_mapping_value(
+            ALEO_CONTRACT.get().unwrap(),
+            program::nodes::NAME,
+            Plaintext::from_str(&node_id.to_input()).map_err(MoriError::decode)?,
+        )
+        .map_err(MoriError::Chain)?;
+
+    if let aleo_rust::Value::Plaintext(p) = value {
+        program::nodes::Value::from_plaintext(&p)
+    } else {
+        Err(MoriError::decode("invalid node value"))
+    }
+}
+
+/// Reads `nodes` mapping entries, `FETCH_CONCURRENCY` at a time.
+fn read_chain_nodes<N: Network>(
+    client: &AleoAPIClient<N>,
+    node_ids: &[u128],
+) -> Vec<Result<GameNode>> {
+    let mut nodes = Vec::with_capacity(node_ids.len());
+    for chunk in node_ids.chunks(FETCH_CONCURRENCY) {
+        std::thread::scope(|s| {
+            let reads = chunk
+                .iter()
+                .map(|id| s.spawn(move || read_chain_node(client, *id)))
+                .collect::<Vec<_>>();
+            for read in reads {
+                let node = read
+                    .join()
+                    .unwrap_or_else(|_| Err(MoriError::execution("mapping read panicked")));
+                nodes.push(node);
+            }
+        });
+    }
+    nodes
+}
+
+/// Compares the fields the program stores, analysis and votes are local only.
+pub fn same_on_chain(a: &GameNode, b: &GameNode) -> bool {
+    a.node_id == b.node_id
+        && a.state == b.state
+        && a.from == b.from
+        && a.game_status == b.game_status
+}
+
+impl<N: Network> Mori<N> {
+    /// Records a `nodes` entry unless a newer one is already mirrored.
+    pub fn mirror_node(
+        &self,
+        txn: &mut DBTransaction,
+        node: &GameNode,
+        height: u32,
+        source: MirrorSource,
+    ) -> Result<()> {
+        if let Some(cur) = txn.get(&self.chain_nodes, &node.node_id)? {
+            if cur.height > height {
+                return Ok(());
+            }
+        }
+        let mirrored = MirroredNode {
+            node: GameNode {
+                valid_movs: vec![],
+                votes: vec![],
+                ..node.clone()
+            },
+            height,
+            source,
+        };
+        txn.insert(&self.chain_nodes, &node.node_id, &mirrored)
+    }
+
+    pub fn mirrored_node(&self, node_id: u128) -> Result<Option<MirroredNode>> {
+        self.chain_nodes.get(&node_id)
+    }
+
+    /// Reads the latest mapping entries from the chain, RPC has no reads at a
+    /// past height. `synced_height` only ages the mirror: entries mirrored
+    /// within `MIRROR_TTL_BLOCKS` of it are served from the mirror, fresh reads
+    /// are mirrored at it.
+    pub fn fetch_chain_nodes(
+        &self,
+        txn: &mut DBTransaction,
+        node_ids: &[u128],
+        synced_height: u32,
+    ) -> Result<Vec<(u128, Result<GameNode>)>> {
+        let min_height = synced_height.saturating_sub(MIRROR_TTL_BLOCKS);
+        let mut seen = HashSet::new();
+        let ids = node_ids
+            .iter()
+            .copied()
+            .filter(|id| seen.insert(*id))
+            .collect::<Vec<_>>();
+
+        let mut nodes = HashMap::new();
+        let mut stale = vec![];
+        for &node_id in &ids {
+            match txn.get(&self.chain_nodes, &node_id)? {
+                Some(cached)
+                    if cached.source == MirrorSource::Rpc && cached.height >= min_height =>
+                {
+                    nodes.insert(node_id, Ok(cached.node));
+                }
+                _ => stale.push(node_id),
+            }
+        }
+        for (node_id, node) in stale
+            .iter()
+            .copied()
+            .zip(read_chain_nodes(&self.aleo_client, &stale))
+        {
+            if let Ok(node) = &node {
+                self.mirror_node(txn, node, synced_height, MirrorSource::Rpc)?;
+            }
+            nodes.insert(node_id, node);
+        }
+
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| nodes.remove(&id).map(|node| (id, node)))
+            .collect())
+    }
+
+    /// Compares every live node against the chain mapping.
+    pub fn reconcile_nodes(&self) -> Result<Vec<NodeMismatch>> {
+        let local = self.mori_nodes.get_all()?;
+        let ids = local.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        let height = self.synced_height()?;
+        let mut txn = self.transaction();
+        let chain = self.fetch_chain_nodes(&mut txn, &ids, height)?;
+        txn.commit()?;
+
+        let mut mismatches = vec![];
+        for ((node_id, local), (_, chain)) in local.into_iter().zip(chain) {
+            let (chain, detail) = match chain {
+                Ok(chain) if same_on_chain(&local, &chain) => continue,
+                Ok(chain) => (Some(chain), "differs from the chain mapping".to_string()),
+                Err(e) => (None, e.to_string()),
+            };
+            tracing::warn!("node {node_id} {detail}");
+            mismatches.push(NodeMismatch {
+                node_id,
+                local,
+                chain,
+                detail,
+            });
+        }
+        tracing::info!("reconciled nodes, {} mismatches", mismatches.len());
+        Ok(mismatches)
+    }
+}
+
+#[test]
+fn test_finalize_effects() {
+    let open = open_game_effect(&OpenGameInputs { node_id: 7 });
+    assert!(open.is_root());
+    assert_eq!(open.state, GameState::zero());
+
+    let next = GameState::zero()
+        .play(29, crate::cores::Side::Black)
+        .unwrap();
+    let inputs = MoveToNextInputs {
+        parent_id: 7,
+        node_id: 8,
+        state: next.raw(),
+        game_status: 0,
+        mov: 29,
+    };
+    let node = move_to_next_effect(&inputs).unwrap();
+    assert_eq!(
+        node.from,
+        NodeEdge {
+            node_id: 7,
+            mov: 29
+        }
+    );
+    assert!(!same_on_chain(&open, &node));
+    assert!(same_on_chain(
+        &node,
+        &GameNode {
+            valid_movs: vec![19, 21, 37],
+            ..node.clone()
+        }
+    ));
+
+    let invalid = MoveToNextInputs {
+        state: 0b11,
+        ..inputs
+    };
+    assert!(move_to_next_effect(&invalid).is_err());
+}
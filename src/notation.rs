@@ -0,0 +1,343 @@
+use std::str::FromStr;
+
+use aleo_rust::Network;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cores::{GameNode, GameState, Side, PASS},
+    error::{MoriError, Result},
+    Mori,
+};
+
+/// Square index to algebraic coordinates, 0 is a8 and 63 is h1.
+pub fn square_to_algebraic(pos: u8) -> Result<String> {
+    match pos {
+        PASS => Ok("pass".to_string()),
+        0..=63 => {
+            let file = (b'a' + pos % 8) as char;
+            let rank = 8 - pos / 8;
+            Ok(format!("{file}{rank}"))
+        }
+        _ => Err(MoriError::validation(format!("invalid square {pos}"))),
+    }
+}
+
+pub fn algebraic_to_square(s: &str) -> Result<u8> {
+    let s = s.to_ascii_lowercase();
+    if s == "pass" || s == "pa" {
+        return Ok(PASS);
+    }
+    match s.as_bytes() {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Ok((b'8' - rank) * 8 + (file - b'a')),
+        _ => Err(MoriError::decode(format!("invalid coordinate {s}"))),
+    }
+}
+
+/// Prints a board as FEN-like text, ranks from 8 to 1 separated by `/`,
+/// runs of empty squares as digits, followed by the side to move.
+pub fn board_to_text(state: &GameState, side: Side) -> String {
+    let ranks = (0..8)
+        .map(|row| {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for col in 0..8 {
+                let disc = match state.side_at(row * 8 + col) {
+                    Some(Side::Black) => 'B',
+                    Some(Side::White) => 'W',
+                    None => {
+                        empty += 1;
+                        continue;
+                    }
+                };
+                if empty > 0 {
+                    rank.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                rank.push(disc);
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            rank
+        })
+        .collect::<Vec<_>>();
+    let side = match side {
+        Side::Black => 'b',
+        Side::White => 'w',
+    };
+    format!("{} {side}", ranks.join("/"))
+}
+
+pub fn board_from_text(text: &str) -> Result<(GameState, Side)> {
+    let (board, side) = text
+        .trim()
+        .split_once(' ')
+        .ok_or(MoriError::decode("missing side to move"))?;
+    let side = match side.trim() {
+        "b" => Side::Black,
+        "w" => Side::White,
+        s => return Err(MoriError::decode(format!("invalid side to move {s}"))),
+    };
+
+    let ranks = board.split('/').collect::<Vec<_>>();
+    if ranks.len() != 8 {
+        return Err(MoriError::decode(format!(
+            "expected 8 ranks, got {}",
+            ranks.len()
+        )));
+    }
+    let mut squares = Vec::with_capacity(64);
+    for rank in ranks {
+        let start = squares.len();
+        for c in rank.chars() {
+            match c {
+                'B' | 'b' => squares.push(1),
+                'W' | 'w' => squares.push(-1),
+                '1'..='8' => squares.resize(squares.len() + (c as usize - '0' as usize), 0),
+                _ => {
+                    return Err(MoriError::decode(format!(
+                        "invalid square {c} in rank {rank}"
+                    )))
+                }
+            }
+        }
+        if squares.len() - start != 8 {
+            return Err(MoriError::decode(format!(
+                "rank {rank} does not have 8 squares"
+            )));
+        }
+    }
+    Ok((GameState::from_vec_i8(&squares)?, side))
+}
+
+/// The full move list of a line, voter and AI moves alternating, passes as [`PASS`].
+/// Only voter moves are stored on the edges, AI moves are recovered from the boards.
+pub fn line_transcript(line: &[GameNode]) -> Result<Vec<u8>> {
+    let mut movs = vec![];
+    for pair in line.windows(2) {
+        let (parent, child) = (&pair[0], &pair[1]);
+        let human = child.from.mov;
+        let after = parent
+            .state
+            .play(human, Side::Black)
+            .ok_or(MoriError::validation(format!(
+                "move {human} is illegal on node {}",
+                parent.node_id
+            )))?;
+        movs.push(human);
+
+        let placed = (0..64)
+            .filter(|&p| after.side_at(p).is_none() && child.state.side_at(p).is_some())
+            .collect::<Vec<_>>();
+        match placed.as_slice() {
+            [] if child.game_status != 0 => {}
+            [] => movs.push(PASS),
+            [ai] => movs.push(*ai),
+            _ => {
+                return Err(MoriError::validation(format!(
+                    "node {} does not follow its parent",
+                    child.node_id
+                )))
+            }
+        }
+    }
+    Ok(movs)
+}
+
+/// Standard Othello transcript like `f5d6c3`, passes are implied.
+pub fn transcript_to_text(movs: &[u8]) -> Result<String> {
+    let mut text = String::new();
+    for &mov in movs.iter().filter(|&&m| m != PASS) {
+        text.push_str(&square_to_algebraic(mov)?);
+    }
+    Ok(text)
+}
+
+/// Parses a transcript, moves may be run together like `f5d6` or separated by
+/// whitespace, and a pass is written `pass` or `pa`.
+pub fn transcript_from_text(text: &str) -> Result<Vec<u8>> {
+    if !text.is_ascii() {
+        return Err(MoriError::decode("transcript is not ASCII"));
+    }
+    let mut movs = vec![];
+    for token in text.split_whitespace() {
+        if token.eq_ignore_ascii_case("pass") {
+            movs.push(PASS);
+            continue;
+        }
+        if token.len() % 2 != 0 {
+            return Err(MoriError::decode(format!(
+                "transcript token {token} has a dangling character"
+            )));
+        }
+        // ASCII, so every byte is a char boundary
+        for i in (0..token.len()).step_by(2) {
+            movs.push(algebraic_to_square(&token[i..i + 2])?);
+        }
+    }
+    Ok(movs)
+}
+
+/// Replays a transcript from the opening position, inserting the passes it implies.
+/// Returns every position of the game, the opening included.
+pub fn replay(movs: &[u8]) -> Result<Vec<GameState>> {
+    let mut states = vec![GameState::zero()];
+    let mut side = Side::Black;
+    for &mov in movs {
+        let cur = *states.last().unwrap();
+        if mov != PASS && cur.legal_moves(side).is_empty() {
+            side = side.opponent();
+        }
+        let next = cur.play(mov, side).ok_or(MoriError::validation(format!(
+            "move {} is illegal for {side:?}",
+            square_to_algebraic(mov)?
+        )))?;
+        if mov != PASS {
+            states.push(next);
+        }
+        side = side.opponent();
+    }
+    Ok(states)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Transcript,
+    Ggf,
+    Pgn,
+    Board,
+}
+
+impl FromStr for ExportFormat {
+    type Err = MoriError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "transcript" => Ok(ExportFormat::Transcript),
+            "ggf" => Ok(ExportFormat::Ggf),
+            "pgn" => Ok(ExportFormat::Pgn),
+            "board" | "fen" => Ok(ExportFormat::Board),
+            _ => Err(MoriError::validation(format!("unknown export format {s}"))),
+        }
+    }
+}
+
+pub fn export(
+    format: ExportFormat,
+    start: &GameState,
+    movs: &[u8],
+    last: &GameState,
+) -> Result<String> {
+    let (black, white) = last.count();
+    let coords = movs
+        .iter()
+        .map(|&m| square_to_algebraic(m))
+        .collect::<Result<Vec<_>>>()?;
+
+    match format {
+        ExportFormat::Transcript => transcript_to_text(movs),
+        ExportFormat::Board => Ok(board_to_text(last, Side::Black)),
+        ExportFormat::Ggf => {
+            let board = (0..64)
+                .map(|p| match start.side_at(p) {
+                    Some(Side::Black) => '*',
+                    Some(Side::White) => 'O',
+                    None => '-',
+                })
+                .collect::<Vec<_>>()
+                .chunks(8)
+                .map(|row| row.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mut ggf = format!(
+                "(;GM[Othello]PC[Mori]PB[voters]PW[ai]RE[{:+}]BO[8 {board} *]",
+                black as i16 - white as i16
+            );
+            for (i, coord) in coords.iter().enumerate() {
+                let player = if i % 2 == 0 { 'B' } else { 'W' };
+                let coord = if coord == "pass" { "PA" } else { coord };
+                ggf.push_str(&format!("{player}[{coord}]"));
+            }
+            ggf.push_str(";)");
+            Ok(ggf)
+        }
+        ExportFormat::Pgn => {
+            let mut pgn = format!(
+                "[Event \"Mori\"]\n[Black \"voters\"]\n[White \"ai\"]\n[Result \"{black}-{white}\"]\n\n"
+            );
+            let turns = coords
+                .chunks(2)
+                .enumerate()
+                .map(|(i, turn)| format!("{}. {}", i + 1, turn.join(" ")))
+                .collect::<Vec<_>>();
+            pgn.push_str(&turns.join(" "));
+            pgn.push('\n');
+            Ok(pgn)
+        }
+    }
+}
+
+impl<N: Network> Mori<N> {
+    /// Exports the line ending in `node_id`, either live or archived.
+    pub fn export_game(&self, node_id: u128, format: ExportFormat) -> Result<Option<String>> {
+        if let Some(node) = self.mori_nodes.get(&node_id)? {
+            let line = self.game_line(&mut self.transaction(), &node)?;
+            let movs = line_transcript(&line)?;
+            return export(format, &line[0].state, &movs, &node.state).map(Some);
+        }
+
+        if let Some(game) = self.finished_games.get(&node_id)? {
+            let start = GameState::zero();
+            return export(format, &start, &game.transcript, &game.final_state).map(Some);
+        }
+
+        Ok(None)
+    }
+}
+
+#[test]
+fn test_algebraic_squares() {
+    assert_eq!(square_to_algebraic(0).unwrap(), "a8");
+    assert_eq!(square_to_algebraic(29).unwrap(), "f5");
+    assert_eq!(square_to_algebraic(63).unwrap(), "h1");
+    assert_eq!(square_to_algebraic(PASS).unwrap(), "pass");
+    assert!(square_to_algebraic(65).is_err());
+
+    for pos in 0..=PASS {
+        let coord = square_to_algebraic(pos).unwrap();
+        assert_eq!(algebraic_to_square(&coord).unwrap(), pos);
+    }
+    assert!(algebraic_to_square("i9").is_err());
+}
+
+#[test]
+fn test_board_text_roundtrip() {
+    let text = board_to_text(&GameState::zero(), Side::Black);
+    assert_eq!(text, "8/8/8/3BW3/3WB3/8/8/8 b");
+
+    let (state, side) = board_from_text(&text).unwrap();
+    assert_eq!(state, GameState::zero());
+    assert_eq!(side, Side::Black);
+    assert!(board_from_text("8/8/8 b").is_err());
+    assert!(board_from_text("8/8/8/3BW3/3WB4/8/8/8 b").is_err());
+}
+
+#[test]
+fn test_transcript_replay() {
+    let movs = transcript_from_text("f5d6 c3d3").unwrap();
+    assert_eq!(movs, vec![29, 19, 42, 43]);
+    assert_eq!(transcript_to_text(&movs).unwrap(), "f5d6c3d3");
+    assert_eq!(
+        transcript_from_text("f5 pass D6 pa").unwrap(),
+        vec![29, PASS, 19, PASS]
+    );
+    assert!(transcript_from_text("f5d").is_err());
+    assert!(transcript_from_text("é4").is_err());
+
+    let states = replay(&movs).unwrap();
+    assert_eq!(states.len(), 5);
+    assert_eq!(states[0], GameState::zero());
+    assert_eq!(states[1].count(), (4, 1));
+    assert!(replay(&[0]).is_err());
+}
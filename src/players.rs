@@ -0,0 +1,228 @@
+use std::collections::{BTreeSet, HashMap};
+
+use aleo_rust::Network;
+use serde::{Deserialize, Serialize};
+
+use crate::{cores::GameNode, db::DBTransaction, error::Result, games::GameOutcome, Mori};
+
+/// Aggregated statistics of one voting address.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PlayerStats {
+    pub address: String,
+    pub games: BTreeSet<u128>, // root node ids of the games the address voted in
+    pub votes_cast: u32,
+    pub majority_votes: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    pub current_streak: u32,
+    pub best_streak: u32,
+}
+
+impl PlayerStats {
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            ..Default::default()
+        }
+    }
+
+    pub fn record_vote(&mut self, game_id: u128) {
+        self.votes_cast += 1;
+        self.games.insert(game_id);
+    }
+
+    pub fn record_majority(&mut self) {
+        self.majority_votes += 1;
+    }
+
+    /// Records the result of a finished game line for the side the address voted for.
+    /// `None` is a draw, which neither extends nor breaks the streak.
+    pub fn record_result(&mut self, won: Option<bool>) {
+        match won {
+            Some(true) => {
+                self.wins += 1;
+                self.current_streak += 1;
+                self.best_streak = self.best_streak.max(self.current_streak);
+            }
+            Some(false) => {
+                self.losses += 1;
+                self.current_streak = 0;
+            }
+            None => self.draws += 1,
+        }
+    }
+
+    pub fn majority_rate(&self) -> f64 {
+        if self.votes_cast == 0 {
+            return 0.0;
+        }
+        self.majority_votes as f64 / self.votes_cast as f64
+    }
+}
+
+/// The most voted move of a node, ties are broken by the smaller move.
+pub fn majority_mov(node: &GameNode) -> Option<u8> {
+    let mut counts = HashMap::new();
+    for v in &node.votes {
+        *counts.entry(v.mov).or_insert(0u32) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|(m1, c1), (m2, c2)| c1.cmp(c2).then(m2.cmp(m1)))
+        .map(|(mov, _)| mov)
+}
+
+/// Orders players by wins, then by how often they voted with the majority.
+pub fn leaderboard(mut players: Vec<PlayerStats>, limit: usize) -> Vec<PlayerStats> {
+    players.sort_by(|a, b| {
+        b.wins
+            .cmp(&a.wins)
+            .then(b.majority_rate().total_cmp(&a.majority_rate()))
+            .then(b.votes_cast.cmp(&a.votes_cast))
+            .then(a.address.cmp(&b.address))
+    });
+    players.truncate(limit);
+    players
+}
+
+impl<N: Network> Mori<N> {
+    pub fn credit_vote(&self, txn: &mut DBTransaction, sender: &str, node_id: u128) -> Result<()> {
+        let game_id = self.game_root(txn, node_id)?;
+        self.update_player(txn, sender, |p| p.record_vote(game_id))
+    }
+
+    /// Credits the voters of the parent's majority move once its child is indexed.
+    ///
+    /// A parent is credited once, for its first child with the majority move:
+    /// every vote asks the AI for a move, so siblings on the same move happen.
+    pub fn credit_majority(&self, txn: &mut DBTransaction, child: &GameNode) -> Result<()> {
+        if child.is_root() {
+            return Ok(());
+        }
+        let parent_id = child.from.node_id;
+        if txn.contain(&self.majority_credits, &parent_id)? {
+            return Ok(());
+        }
+        let Some(parent) = txn.get(&self.mori_nodes, &parent_id)? else {
+            return Ok(());
+        };
+        if majority_mov(&parent) != Some(child.from.mov) {
+            return Ok(());
+        }
+
+        for v in parent.votes.iter().filter(|v| v.mov == child.from.mov) {
+            self.update_player(txn, &v.sender, |p| p.record_majority())?;
+        }
+        txn.insert(&self.majority_credits, &parent_id, &child.from.mov)
+    }
+
+    /// Settles the result of a finished game line for every address that voted along it.
+    pub fn settle_players(
+        &self,
+        txn: &mut DBTransaction,
+        voters: &[String],
+        outcome: GameOutcome,
+    ) -> Result<()> {
+        let won = outcome.voters_won();
+        for voter in voters {
+            self.update_player(txn, voter, |p| p.record_result(won))?;
+        }
+        Ok(())
+    }
+
+    pub fn get_player(&self, address: &str) -> Result<Option<PlayerStats>> {
+        self.players.get(&address.to_string())
+    }
+
+    pub fn get_leaderboard(&self, limit: usize) -> Result<Vec<PlayerStats>> {
+        let players = self.players.get_all()?.into_iter().map(|(_, p)| p);
+        Ok(leaderboard(players.collect(), limit))
+    }
+
+    fn update_player(
+        &self,
+        txn: &mut DBTransaction,
+        address: &str,
+        f: impl FnOnce(&mut PlayerStats),
+    ) -> Result<()> {
+        let address = address.to_string();
+        txn.update(&self.players, &address, |player| {
+            f(player.get_or_insert_with(|| PlayerStats::new(address.clone())))
+        })
+    }
+}
+
+#[test]
+fn test_player_streaks() {
+    let mut player = PlayerStats::new("aleo1player".to_string());
+    for won in [
+        Some(true),
+        Some(true),
+        None,
+        Some(true),
+        Some(false),
+        Some(true),
+    ] {
+        player.record_result(won);
+    }
+
+    assert_eq!((player.wins, player.losses, player.draws), (4, 1, 1));
+    assert_eq!(player.best_streak, 3);
+    assert_eq!(player.current_streak, 1);
+}
+
+#[test]
+fn test_majority_and_leaderboard() {
+    use crate::cores::{GameState, NodeEdge, Vote};
+
+    let vote = |sender: &str, mov| Vote {
+        sender: sender.into(),
+        node_id: 1,
+        mov,
+    };
+    let node = GameNode {
+        node_id: 1,
+        state: GameState::zero(),
+        from: NodeEdge { node_id: 0, mov: 0 },
+        game_status: 0,
+        valid_movs: vec![19, 26, 37],
+        votes: vec![vote("a", 26), vote("b", 19), vote("c", 19), vote("d", 26)],
+    };
+    assert_eq!(majority_mov(&node), Some(19));
+
+    let mut a = PlayerStats::new("a".to_string());
+    a.record_vote(1);
+    a.record_result(Some(true));
+    let mut b = PlayerStats::new("b".to_string());
+    b.record_vote(1);
+    b.record_majority();
+    b.record_result(Some(true));
+    let c = PlayerStats::new("c".to_string());
+
+    let board = leaderboard(vec![c, a, b], 2);
+    let addresses = board.iter().map(|p| p.address.as_str()).collect::<Vec<_>>();
+    assert_eq!(addresses, vec!["b", "a"]);
+}
+
+#[test]
+fn test_credit_majority_once() {
+    use crate::{cores::Vote, test_node};
+
+    let (mori, _rx) = Mori::<aleo_rust::Testnet3>::test_open().unwrap();
+    let mut parent = test_node(1, 0);
+    parent.votes.push(Vote {
+        sender: "a".into(),
+        node_id: 1,
+        mov: 19,
+    });
+    mori.mori_nodes.insert(&1, &parent).unwrap();
+
+    // two votes asked the AI twice, both children landed on the majority move
+    let mut txn = mori.transaction();
+    mori.credit_majority(&mut txn, &test_node(2, 1)).unwrap();
+    mori.credit_majority(&mut txn, &test_node(3, 1)).unwrap();
+    txn.commit().unwrap();
+    let player = mori.get_player("a").unwrap().unwrap();
+    assert_eq!(player.majority_votes, 1);
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use aleo_rust::{AleoAPIClient, Network, Plaintext, PrivateKey, Record, ViewKey};
+
+use crate::{
+    error::{MoriError, Result},
+    Execution, FEE_NUM,
+};
+
+/// Minimum time between two record scans, every idle worker asks for one.
+const REFILL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Routes executions to a fixed set of workers.
+///
+/// Executions that belong to the same game always land on the same worker, so
+/// the moves of a game tree are proved and submitted in order, while different
+/// games are proved in parallel.
+pub struct WorkerPool {
+    senders: Vec<mpsc::Sender<Execution>>,
+    next: AtomicUsize,
+}
+
+impl WorkerPool {
+    pub fn new(senders: Vec<mpsc::Sender<Execution>>) -> Self {
+        assert!(!senders.is_empty(), "worker pool needs at least one worker");
+        Self {
+            senders,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Picks the worker for a game, or the next worker in turn if the
+    /// execution does not belong to a game yet.
+    pub fn worker_for(&self, game_id: Option<u128>) -> usize {
+        match game_id {
+            Some(id) => (id % self.senders.len() as u128) as usize,
+            None => self.next.fetch_add(1, Ordering::Relaxed) % self.senders.len(),
+        }
+    }
+
+    pub fn dispatch(&self, game_id: Option<u128>, exec: Execution) -> Result<()> {
+        let worker = self.worker_for(game_id);
+        tracing::debug!("dispatch execution {exec:?} to worker {worker}");
+        self.senders[worker]
+            .send(exec)
+            .map_err(|e| MoriError::execution(format!("worker {worker} channel closed: {e}")))
+    }
+}
+
+/// Shares private fee records between workers.
+///
+/// A record handed out by [`FeeAllocator::acquire`] is never handed out
+/// again, so two workers never pay with the same record. Once an execution was
+/// submitted with it the record may be spent even if the call failed, so it is
+/// only given back through [`FeeAllocator::release`] when it was never used.
+/// When no record is available the workers fall back to public fees.
+#[derive(Clone)]
+pub struct FeeAllocator<N: Network> {
+    inner: Arc<Mutex<FeeRecords<N>>>,
+}
+
+struct FeeRecords<N: Network> {
+    free: VecDeque<Record<N, Plaintext<N>>>,
+    // records that were added once, leased or spent ones are not added again
+    seen: HashSet<String>,
+    scanned_height: u32,
+    last_refill: Option<Instant>,
+}
+
+pub struct FeeLease<N: Network> {
+    pub record: Record<N, Plaintext<N>>,
+}
+
+impl<N: Network> FeeAllocator<N> {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(FeeRecords {
+                free: VecDeque::new(),
+                seen: HashSet::new(),
+                scanned_height: 0,
+                last_refill: None,
+            })),
+        }
+    }
+
+    pub fn available(&self) -> usize {
+        self.inner.lock().unwrap().free.len()
+    }
+
+    /// Microcredits held by the records that are not leased.
+    pub fn balance(&self) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .free
+            .iter()
+            .filter_map(|record| record.microcredits().ok())
+            .sum()
+    }
+
+    pub fn acquire(&self) -> Option<FeeLease<N>> {
+        let mut inner = self.inner.lock().unwrap();
+        let record = inner.free.pop_front()?;
+        Some(FeeLease { record })
+    }
+
+    /// Gives back a record that was never submitted with an execution.
+    pub fn release(&self, lease: FeeLease<N>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.free.push_back(lease.record);
+    }
+
+    pub fn add_record(&self, record: Record<N, Plaintext<N>>) {
+        let mut inner = self.inner.lock().unwrap();
+        let key = record.to_string();
+        if inner.seen.insert(key) {
+            inner.free.push_back(record);
+        }
+    }
+
+    /// Skips the blocks below `height` when scanning for records, they
+    /// cannot hold records of the account.
+    pub fn scan_from(&self, height: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.scanned_height = inner.scanned_height.max(height);
+    }
+
+    /// Scans the blocks since the last refill for unspent credits records that
+    /// can pay for one execution. Does nothing if the last scan is recent.
+    pub fn refill(
+        &self,
+        client: &AleoAPIClient<N>,
+        pk: &PrivateKey<N>,
+        vk: &ViewKey<N>,
+    ) -> Result<()> {
+        let from = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner
+                .last_refill
+                .is_some_and(|last| last.elapsed() < REFILL_INTERVAL)
+            {
+                return Ok(());
+            }
+            inner.last_refill = Some(Instant::now());
+            inner.scanned_height
+        };
+        let latest = client.latest_height().map_err(MoriError::Chain)?;
+        if from >= latest {
+            return Ok(());
+        }
+
+        let records = client
+            .get_unspent_records(pk, from..latest, None, None)
+            .map_err(MoriError::Chain)?;
+        for (_, record) in records {
+            let record = record.decrypt(vk).map_err(MoriError::decode)?;
+            if record.microcredits().map_err(MoriError::decode)? >= FEE_NUM {
+                self.add_record(record);
+            }
+        }
+
+        self.inner.lock().unwrap().scanned_height = latest;
+        tracing::info!(
+            "fee records refilled up to height {latest}, {} available",
+            self.available()
+        );
+        Ok(())
+    }
+}
+
+impl<N: Network> Default for FeeAllocator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_worker_for_game_is_stable() {
+    let (senders, _receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| mpsc::channel()).unzip();
+    let pool = WorkerPool::new(senders);
+
+    for game_id in [0u128, 1, 7, 1234567890123456789] {
+        let worker = pool.worker_for(Some(game_id));
+        assert!(worker < pool.size());
+        assert_eq!(worker, pool.worker_for(Some(game_id)));
+    }
+
+    let picked = (0..8).map(|_| pool.worker_for(None)).collect::<Vec<_>>();
+    assert_eq!(picked, vec![0, 1, 2, 3, 0, 1, 2, 3]);
+}
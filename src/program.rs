@@ -0,0 +1,223 @@
+//! Typed bindings for the functions and mappings of the deployed program,
+//! generated by build.rs from `program/mori.aleo`.
+
+use std::str::FromStr;
+
+use aleo_rust::{
+    AleoAPIClient, Ciphertext, Field, Network, Plaintext, Program, ProgramID, Record, Value,
+    ViewKey, U16,
+};
+use serde::{Deserialize, Serialize};
+use snarkvm_ledger::{Input, Output, Transition};
+use snarkvm_utilities::ToBits;
+
+use crate::{
+    error::{DecodeError, MoriError, Result},
+    utils::{FromAleoPlaintext, FromAleoRecord, ToAleoInput},
+};
+
+include!(concat!(env!("OUT_DIR"), "/mori_program.rs"));
+
+/// The program the bindings are generated from.
+pub const SOURCE: &str = include_str!("../program/mori.aleo");
+
+/// Whether `program/mori.aleo` is the program deployed as `program_name`.
+/// The programs are compared parsed, so comments and layout do not matter.
+pub fn matches_deployed<N: Network>(client: &AleoAPIClient<N>, program_name: &str) -> Result<bool> {
+    let source = SOURCE.replacen(
+        &format!("program {PROGRAM_ID};"),
+        &format!("program {program_name};"),
+        1,
+    );
+    let local = Program::<N>::from_str(&source).map_err(MoriError::validation)?;
+    let id = ProgramID::<N>::from_str(program_name).map_err(MoriError::validation)?;
+    let deployed = client.get_program(id).map_err(MoriError::Chain)?;
+    Ok(local == deployed)
+}
+
+fn check_len(function: &str, values: &str, expected: usize, got: usize) -> Result<()> {
+    if expected != got {
+        return Err(MoriError::Malformed(DecodeError::Length {
+            function: function.to_string(),
+            values: values.to_string(),
+            expected,
+            got,
+        }));
+    }
+    Ok(())
+}
+
+fn plaintext_value<N: Network>(value: &Value<N>) -> Result<&Plaintext<N>> {
+    match value {
+        Value::Plaintext(p) => Ok(p),
+        Value::Record(_) => Err(MoriError::decode("expected a plaintext, got a record")),
+    }
+}
+
+fn record_value<N: Network>(value: &Value<N>) -> Result<&Record<N, Plaintext<N>>> {
+    match value {
+        Value::Record(r) => Ok(r),
+        Value::Plaintext(_) => Err(MoriError::decode("expected a record, got a plaintext")),
+    }
+}
+
+/// Decrypts the private input or output at `index`, the outputs are indexed
+/// after the inputs. Only the caller of the transition, which is us for
+/// `open_game` and `move_to_next`, can derive the transition view key.
+fn decrypt_private<N: Network>(
+    t: &Transition<N>,
+    vk: &ViewKey<N>,
+    index: usize,
+    ciphertext: &Ciphertext<N>,
+) -> Option<Plaintext<N>> {
+    let program_id = t.program_id();
+    let function_id = N::hash_bhp1024(
+        &(
+            U16::<N>::new(N::ID),
+            program_id.name(),
+            program_id.network(),
+            t.function_name(),
+        )
+            .to_bits_le(),
+    )
+    .ok()?;
+    let tvk = (*t.tpk() * **vk).to_x_coordinate();
+    let view_key = N::hash_psd4(&[function_id, tvk, Field::from_u16(index as u16)]).ok()?;
+    ciphertext.decrypt_symmetric(view_key).ok()
+}
+
+fn unreadable<N: Network>(
+    t: &Transition<N>,
+    values: &str,
+    index: usize,
+    encrypted: bool,
+) -> MoriError {
+    let (function, values) = (t.function_name().to_string(), values.to_string());
+    MoriError::Malformed(if encrypted {
+        DecodeError::Undecryptable {
+            function,
+            values,
+            index,
+        }
+    } else {
+        DecodeError::NoValue {
+            function,
+            values,
+            index,
+        }
+    })
+}
+
+/// The inputs of a transition, private ones decrypted with our view key.
+pub fn input_values<N: Network>(t: &Transition<N>, vk: &ViewKey<N>) -> Result<Vec<Value<N>>> {
+    let error = |index, encrypted| unreadable(t, "inputs", index, encrypted);
+    t.inputs()
+        .iter()
+        .enumerate()
+        .map(|(i, input)| match input {
+            Input::Constant(_, Some(p)) | Input::Public(_, Some(p)) => {
+                Ok(Value::Plaintext(p.clone()))
+            }
+            Input::Private(_, Some(c)) => decrypt_private(t, vk, i, c)
+                .map(Value::Plaintext)
+                .ok_or_else(|| error(i, true)),
+            // records are only committed to by their serial number
+            _ => Err(error(i, false)),
+        })
+        .collect()
+}
+
+/// The outputs of a transition, private ones and our records decrypted.
+pub fn output_values<N: Network>(t: &Transition<N>, vk: &ViewKey<N>) -> Result<Vec<Value<N>>> {
+    let error = |index, encrypted| unreadable(t, "outputs", index, encrypted);
+    let offset = t.inputs().len();
+    t.outputs()
+        .iter()
+        .enumerate()
+        .map(|(i, output)| match output {
+            Output::Constant(_, Some(p)) | Output::Public(_, Some(p)) => {
+                Ok(Value::Plaintext(p.clone()))
+            }
+            Output::Private(_, Some(c)) => decrypt_private(t, vk, offset + i, c)
+                .map(Value::Plaintext)
+                .ok_or_else(|| error(i, true)),
+            Output::Record(_, _, Some(r)) => {
+                r.decrypt(vk).map(Value::Record).map_err(|_| error(i, true))
+            }
+            _ => Err(error(i, false)),
+        })
+        .collect()
+}
+
+/// The arguments the transition passes to its finalize block.
+pub fn finalize_values<N: Network>(t: &Transition<N>) -> Vec<Value<N>> {
+    t.finalize().cloned().unwrap_or_default()
+}
+
+/// Decodes a transition from its inputs. When they are not readable, e.g. a
+/// record input or a private input of someone else, falls back to the finalize
+/// arguments and then to the outputs. Returns the error of the inputs if no
+/// source decodes.
+pub fn decode_transition<N: Network, T>(
+    t: &Transition<N>,
+    vk: &ViewKey<N>,
+    decode: impl Fn(&[Value<N>]) -> Result<T>,
+) -> Result<T> {
+    let error = match input_values(t, vk).and_then(|v| decode(&v)) {
+        Ok(decoded) => return Ok(decoded),
+        Err(e) => e,
+    };
+
+    let fallbacks = [
+        ("finalize", Ok(finalize_values(t))),
+        ("outputs", output_values(t, vk)),
+    ];
+    for (source, values) in fallbacks {
+        if let Ok(decoded) = values.and_then(|v| decode(&v)) {
+            tracing::warn!(
+                "decoded {} transition {} from its {source}, inputs failed: {error}",
+                t.function_name(),
+                t.id()
+            );
+            return Ok(decoded);
+        }
+    }
+    Err(error)
+}
+
+#[test]
+fn test_program_bindings() {
+    use aleo_rust::Testnet3;
+
+    assert_eq!(PROGRAM_ID, "mori.aleo");
+    let program = Program::<Testnet3>::from_str(SOURCE).unwrap();
+    assert_eq!(program.id().to_string(), PROGRAM_ID);
+    for f in Function::ALL {
+        assert_eq!(Function::from_str(f.name()).unwrap(), f);
+    }
+    assert!(Function::from_str("transfer").is_err());
+    assert_eq!(nodes::NAME, "nodes");
+
+    let inputs = MoveToNextInputs {
+        parent_id: 1,
+        node_id: 2,
+        state: crate::cores::GameState::zero().raw(),
+        game_status: 0,
+        mov: 29,
+    };
+    let values = inputs
+        .to_inputs()
+        .iter()
+        .map(|s| Value::<Testnet3>::from_str(s).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(MoveToNextInputs::from_values(&values).unwrap(), inputs);
+    assert!(matches!(
+        MoveToNextInputs::from_values(&values[..4]),
+        Err(MoriError::Malformed(DecodeError::Length {
+            expected: 5,
+            got: 4,
+            ..
+        }))
+    ));
+    assert!(OpenGameInputs::from_values(&values[..1]).is_ok());
+}
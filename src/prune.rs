@@ -0,0 +1,456 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use aleo_rust::Network;
+use serde::{Deserialize, Serialize};
+
+use crate::{cores::GameNode, db::DBTransaction, error::Result, filter::BlockMeta, Mori};
+
+/// Writes committed per transaction by a prune run.
+const PRUNE_CHUNK: usize = 512;
+
+/// What a prune run removes, the leaf TTLs are off when unset.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PruneOptions {
+    /// Prune the open leaves nobody voted on for this many blocks.
+    pub leaf_ttl_blocks: Option<u32>,
+    /// Prune the open leaves nobody voted on for this many seconds, measured
+    /// from the timestamp of the block that created them.
+    pub leaf_ttl_secs: Option<u64>,
+    /// Report what would be removed without removing it.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl PruneOptions {
+    /// Whether a leaf created at `birth` has outlived one of the TTLs.
+    pub fn expired(&self, birth: &BlockMeta, height: u32, now: i64) -> bool {
+        let by_blocks = self
+            .leaf_ttl_blocks
+            .is_some_and(|ttl| height.saturating_sub(birth.height) >= ttl);
+        let by_time = self
+            .leaf_ttl_secs
+            .is_some_and(|ttl| now.saturating_sub(birth.timestamp) >= ttl as i64);
+        by_blocks || by_time
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PruneReport {
+    pub dry_run: bool,
+    /// Synced height the leaf ages were measured at.
+    pub height: u32,
+    pub pruned_leaves: Vec<u128>,
+    /// Voted nodes above a pruned leaf that led nowhere else, up to the first
+    /// node shared with another branch.
+    pub pruned_ancestors: Vec<u128>,
+    /// Mirror, birth, credit, timeout and position entries of nodes that are
+    /// no longer live.
+    pub compacted_entries: usize,
+    /// Votes on nodes that are neither live nor part of a finished game.
+    pub orphan_votes: usize,
+    pub elapsed_ms: u64,
+}
+
+/// Totals of the prune runs since startup, dry runs are not counted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PruneMetrics {
+    pub runs: u64,
+    pub failures: u64,
+    pub pruned_leaves: u64,
+    pub pruned_ancestors: u64,
+    pub compacted_entries: u64,
+    pub orphan_votes: u64,
+    /// The report of the last run, dry or not.
+    pub last_report: Option<PruneReport>,
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl<N: Network> Mori<N> {
+    /// Removes the abandoned leaves with the branch that only led to them,
+    /// then the leftovers of the nodes that are no longer live: finished games keep only their `FinishedGame` archive
+    /// and the votes on their nodes.
+    ///
+    /// Removals are committed every `PRUNE_CHUNK` writes. A vote or a move
+    /// landing on a pruned node meanwhile fails the run at that chunk instead
+    /// of being lost, the chunks before it stay committed.
+    pub fn prune(&self, options: PruneOptions) -> Result<PruneReport> {
+        let started = Instant::now();
+        let result = self.prune_inner(options);
+        let mut metrics = self.prune_metrics.lock().unwrap_or_else(|e| e.into_inner());
+        match &result {
+            Ok(report) if !report.dry_run => {
+                metrics.runs += 1;
+                metrics.pruned_leaves += report.pruned_leaves.len() as u64;
+                metrics.pruned_ancestors += report.pruned_ancestors.len() as u64;
+                metrics.compacted_entries += report.compacted_entries as u64;
+                metrics.orphan_votes += report.orphan_votes as u64;
+            }
+            Ok(_) => {}
+            Err(_) => metrics.failures += 1,
+        }
+        let mut report = result?;
+        report.elapsed_ms = started.elapsed().as_millis() as u64;
+        metrics.last_report = Some(report.clone());
+        Ok(report)
+    }
+
+    pub fn prune_metrics(&self) -> PruneMetrics {
+        self.prune_metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Prunes every `interval_secs` on a background thread.
+    pub fn schedule_prune(&self, options: PruneOptions, interval_secs: u64) {
+        if interval_secs == 0 {
+            return;
+        }
+        let self_clone = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(interval_secs));
+            match self_clone.prune(options) {
+                Ok(report) => tracing::info!(
+                    "pruned {} leaves, {} entries, {} orphan votes{}",
+                    report.pruned_leaves.len(),
+                    report.compacted_entries,
+                    report.orphan_votes,
+                    if report.dry_run { " (dry run)" } else { "" }
+                ),
+                Err(e) => tracing::error!("prune error: {:?}", e),
+            }
+        });
+    }
+
+    fn prune_inner(&self, options: PruneOptions) -> Result<PruneReport> {
+        let height = self.network_height.get(&self.network_key)?.unwrap_or(0);
+        let now = unix_now();
+        let mut report = PruneReport {
+            dry_run: options.dry_run,
+            height,
+            ..Default::default()
+        };
+        let mut txn = self.transaction();
+        let mut pending = 0;
+
+        for (node_id, node) in &self.nodes_by_status(0)? {
+            if !self.is_abandoned(node)? {
+                continue;
+            }
+            let Some(birth) = txn.get(&self.node_births, node_id)? else {
+                // nodes indexed before births were recorded start aging now
+                let birth = BlockMeta {
+                    height,
+                    timestamp: now,
+                };
+                txn.insert(&self.node_births, node_id, &birth)?;
+                self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+                continue;
+            };
+            if !options.expired(&birth, height, now) {
+                continue;
+            }
+            if txn.get(&self.mori_nodes, node_id)?.as_ref() != Some(node) {
+                continue;
+            }
+            txn.remove(&self.mori_nodes, node_id)?;
+            report.pruned_leaves.push(*node_id);
+            self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+
+            // walk up while the parent has no other branch, like `archive_game`
+            let mut removed = vec![*node_id];
+            let mut cur = node.clone();
+            while !cur.is_root() {
+                let Some(parent) = txn.get(&self.mori_nodes, &cur.from.node_id)? else {
+                    break;
+                };
+                let children = txn.query_keys(&self.mori_nodes, &self.children, &parent.node_id)?;
+                if children.iter().any(|c| !removed.contains(c)) {
+                    break;
+                }
+                txn.remove(&self.mori_nodes, &parent.node_id)?;
+                report.pruned_ancestors.push(parent.node_id);
+                removed.push(parent.node_id);
+                self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+                cur = parent;
+            }
+        }
+
+        // `contain` reads the live nodes in the transaction, so a node created
+        // or archived while the leftovers are collected fails the commit
+        for entry in self.chain_nodes.iter() {
+            let (node_id, _) = entry?;
+            if !txn.contain(&self.mori_nodes, &node_id)? {
+                txn.remove(&self.chain_nodes, &node_id)?;
+                report.compacted_entries += 1;
+                self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+            }
+        }
+        for entry in self.node_births.iter() {
+            let (node_id, _) = entry?;
+            if !txn.contain(&self.mori_nodes, &node_id)? {
+                txn.remove(&self.node_births, &node_id)?;
+                report.compacted_entries += 1;
+                self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+            }
+        }
+        for entry in self.majority_credits.iter() {
+            let (node_id, _) = entry?;
+            if !txn.contain(&self.mori_nodes, &node_id)? {
+                txn.remove(&self.majority_credits, &node_id)?;
+                report.compacted_entries += 1;
+                self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+            }
+        }
+        for entry in self.timed_out_turns.iter() {
+            let (node_id, _) = entry?;
+            if !txn.contain(&self.mori_nodes, &node_id)? {
+                txn.remove(&self.timed_out_turns, &node_id)?;
+                report.compacted_entries += 1;
+                self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+            }
+        }
+        for entry in self.positions.iter() {
+            let (hash, _) = entry?;
+            // read again in the transaction, a concurrent sync may add a node
+            let ids = txn.get(&self.positions, &hash)?.unwrap_or_default();
+            let mut live = vec![];
+            for id in &ids {
+                if txn.contain(&self.mori_nodes, id)? {
+                    live.push(*id);
+                }
+            }
+            report.compacted_entries += ids.len() - live.len();
+            match (live.is_empty(), live.len() < ids.len()) {
+                (true, _) => txn.remove(&self.positions, &hash)?,
+                (false, true) => txn.insert(&self.positions, &hash, &live)?,
+                (false, false) => continue,
+            }
+            self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+        }
+
+        let archived = self
+            .finished_games
+            .iter()
+            .map(|entry| entry.map(|(_, game)| game.nodes))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<HashSet<_>>();
+        for entry in self.votes.iter() {
+            let (key, _) = entry?;
+            let node_id = key.0;
+            if archived.contains(&node_id) || txn.contain(&self.mori_nodes, &node_id)? {
+                continue;
+            }
+            txn.remove(&self.votes, &key)?;
+            report.orphan_votes += 1;
+            self.prune_chunk(&mut txn, &mut pending, options.dry_run)?;
+        }
+
+        if !options.dry_run {
+            txn.commit()?;
+        }
+        Ok(report)
+    }
+
+    /// Counts one write of `txn` and commits it once `PRUNE_CHUNK` are
+    /// pending. A dry run keeps every write so later steps see them.
+    fn prune_chunk(
+        &self,
+        txn: &mut DBTransaction,
+        pending: &mut usize,
+        dry_run: bool,
+    ) -> Result<()> {
+        *pending += 1;
+        if dry_run || *pending < PRUNE_CHUNK {
+            return Ok(());
+        }
+        *pending = 0;
+        std::mem::replace(txn, self.transaction()).commit()
+    }
+
+    /// An open leaf that was never voted on and the turn timers gave up on,
+    /// if they are on.
+    fn is_abandoned(&self, node: &GameNode) -> Result<bool> {
+        if node.game_status != 0 || !node.votes.is_empty() || self.turn_pending(node)? {
+            return Ok(false);
+        }
+        if !self
+            .mori_nodes
+            .query_keys(&self.children, &node.node_id)?
+            .is_empty()
+        {
+            return Ok(false);
+        }
+        Ok(self.votes.scan_prefix(&node.node_id).next().is_none())
+    }
+}
+
+#[test]
+fn test_prune() {
+    use crate::{
+        cores::{GameState, Vote, VoteRecord},
+        games::{FinishedGame, GameOutcome},
+        test_node,
+    };
+
+    let (mori, _rx) = Mori::<aleo_rust::Testnet3>::test_open().unwrap();
+    let vote = |node_id| VoteRecord {
+        vote: Vote {
+            sender: "at1voter".into(),
+            node_id,
+            mov: 19,
+        },
+        transition_id: format!("at1transition{node_id}"),
+        block_height: 1,
+        block_timestamp: 0,
+        output_index: 0,
+        commitment: String::new(),
+    };
+    let born = |height| BlockMeta {
+        height,
+        timestamp: 0,
+    };
+
+    // 1 was voted on and led to 2, 3 is an abandoned game, 4 is fresh, 5 has
+    // no birth yet, 6 was archived
+    for (id, parent, birth) in [(1, 0, 1), (2, 1, 90), (3, 0, 1), (4, 0, 95)] {
+        mori.mori_nodes.insert(&id, &test_node(id, parent)).unwrap();
+        mori.node_births.insert(&id, &born(birth)).unwrap();
+    }
+    mori.mori_nodes.insert(&5, &test_node(5, 0)).unwrap();
+    let record = vote(1);
+    mori.votes
+        .insert(&(1, record.transition_id.clone(), 0), &record)
+        .unwrap();
+    for id in [6, 7] {
+        let record = vote(id);
+        mori.votes
+            .insert(&(id, record.transition_id.clone(), 0), &record)
+            .unwrap();
+    }
+    let game = FinishedGame {
+        root_id: 6,
+        leaf_id: 6,
+        outcome: GameOutcome::Draw,
+        score: (2, 2),
+        game_status: 3,
+        final_state: GameState::zero(),
+        nodes: vec![6],
+        moves: vec![],
+        transcript: vec![],
+        participants: vec![],
+    };
+    mori.finished_games.insert(&6, &game).unwrap();
+    mori.node_births.insert(&6, &born(1)).unwrap();
+    mori.positions.insert(&42, &vec![3, 4, 6]).unwrap();
+    mori.set_cur_height(100).unwrap();
+
+    let options = PruneOptions {
+        leaf_ttl_blocks: Some(50),
+        leaf_ttl_secs: None,
+        dry_run: true,
+    };
+    let dry = mori.prune(options).unwrap();
+    assert_eq!(dry.pruned_leaves, vec![3]);
+    // births of 3 and 6, and 3 and 6 in the position
+    assert_eq!(dry.compacted_entries, 4);
+    assert_eq!(dry.orphan_votes, 1);
+    assert!(mori.mori_nodes.contain(&3).unwrap());
+    assert_eq!(mori.prune_metrics().runs, 0);
+
+    let report = mori
+        .prune(PruneOptions {
+            dry_run: false,
+            ..options
+        })
+        .unwrap();
+    assert_eq!(report.pruned_leaves, dry.pruned_leaves);
+    assert!(!mori.mori_nodes.contain(&3).unwrap());
+    assert!(mori.node_births.contain(&5).unwrap());
+    assert_eq!(mori.positions.get(&42).unwrap(), Some(vec![4]));
+    let voted = mori
+        .votes
+        .iter()
+        .map(|e| e.unwrap().0 .0)
+        .collect::<Vec<_>>();
+    assert_eq!(voted, vec![1, 6]);
+
+    let metrics = mori.prune_metrics();
+    assert_eq!((metrics.runs, metrics.pruned_leaves), (1, 1));
+    assert_eq!(metrics.last_report, Some(report));
+
+    // nothing is removed without a TTL
+    let again = mori.prune(PruneOptions::default()).unwrap();
+    assert!(again.pruned_leaves.is_empty());
+    assert_eq!(again.compacted_entries + again.orphan_votes, 0);
+}
+
+#[test]
+fn test_prune_voted_branch() {
+    use crate::{
+        cores::{Vote, VoteRecord},
+        test_node,
+    };
+
+    let (mori, _rx) = Mori::<aleo_rust::Testnet3>::test_open().unwrap();
+    let born = BlockMeta {
+        height: 1,
+        timestamp: 0,
+    };
+
+    // the voters played 10 and 11, then left the game at 12; 20 has a fresh
+    // branch 22 next to the abandoned 21
+    for (id, parent) in [(10, 0), (11, 10), (12, 11), (20, 0), (21, 20), (22, 20)] {
+        mori.mori_nodes.insert(&id, &test_node(id, parent)).unwrap();
+        mori.node_births.insert(&id, &born).unwrap();
+    }
+    mori.node_births
+        .insert(&22, &BlockMeta { height: 95, ..born })
+        .unwrap();
+    for id in [10, 11, 20] {
+        let record = VoteRecord {
+            vote: Vote {
+                sender: "at1voter".into(),
+                node_id: id,
+                mov: 19,
+            },
+            transition_id: format!("at1transition{id}"),
+            block_height: 1,
+            block_timestamp: 0,
+            output_index: 0,
+            commitment: String::new(),
+        };
+        mori.votes
+            .insert(&(id, record.transition_id.clone(), 0), &record)
+            .unwrap();
+    }
+    mori.set_cur_height(100).unwrap();
+
+    let report = mori
+        .prune(PruneOptions {
+            leaf_ttl_blocks: Some(50),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(report.pruned_leaves, vec![12, 21]);
+    assert_eq!(report.pruned_ancestors, vec![11, 10]);
+    assert_eq!(report.orphan_votes, 2);
+    for id in [10, 11, 12, 21] {
+        assert!(!mori.mori_nodes.contain(&id).unwrap());
+    }
+    for id in [20, 22] {
+        assert!(mori.mori_nodes.contain(&id).unwrap());
+    }
+    assert_eq!(mori.prune_metrics().pruned_ancestors, 2);
+}
@@ -0,0 +1,395 @@
+use std::{collections::HashMap, io::Write};
+
+use aleo_rust::Network;
+use flate2::{write::ZlibEncoder, Compression, Crc};
+
+use crate::{
+    cores::{GameNode, Side, PASS},
+    error::Result,
+    notation::line_transcript,
+    Mori,
+};
+
+const CELL: u32 = 60;
+const MARGIN: u32 = 30;
+const SIZE: u32 = CELL * 8 + MARGIN * 2;
+
+const BACKGROUND: [u8; 3] = [0xf4, 0xf1, 0xea];
+const BOARD: [u8; 3] = [0x2e, 0x7d, 0x32];
+const GRID: [u8; 3] = [0x1b, 0x4d, 0x1f];
+const BLACK: [u8; 3] = [0x11, 0x11, 0x11];
+const WHITE: [u8; 3] = [0xfa, 0xfa, 0xfa];
+const LAST_MOVE: [u8; 3] = [0xe5, 0x39, 0x35];
+const VALID_MOVE: [u8; 3] = [0xa5, 0xd6, 0xa7];
+const TEXT: [u8; 3] = [0x33, 0x33, 0x33];
+const VOTES: [u8; 3] = [0xff, 0xeb, 0x3b];
+
+/// What is drawn on top of the board of a node.
+#[derive(Debug, Clone)]
+pub struct BoardView {
+    pub node: GameNode,
+    pub last_move: Option<u8>,
+    pub votes: HashMap<u8, usize>,
+}
+
+impl BoardView {
+    pub fn new(node: GameNode, last_move: Option<u8>) -> Self {
+        let mut votes = HashMap::new();
+        for v in &node.votes {
+            *votes.entry(v.mov).or_default() += 1;
+        }
+        Self {
+            node,
+            last_move: last_move.filter(|&m| m != PASS),
+            votes,
+        }
+    }
+
+    fn center(pos: u8) -> (u32, u32) {
+        let (row, col) = (pos as u32 / 8, pos as u32 % 8);
+        (
+            MARGIN + col * CELL + CELL / 2,
+            MARGIN + row * CELL + CELL / 2,
+        )
+    }
+
+    pub fn svg(&self) -> String {
+        let hex = |c: [u8; 3]| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]);
+        let board = CELL * 8;
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{SIZE}" height="{SIZE}" viewBox="0 0 {SIZE} {SIZE}" font-family="sans-serif">"#
+        );
+        svg.push_str(&format!(
+            r#"<rect width="{SIZE}" height="{SIZE}" fill="{}"/><rect x="{MARGIN}" y="{MARGIN}" width="{board}" height="{board}" fill="{}"/>"#,
+            hex(BACKGROUND),
+            hex(BOARD)
+        ));
+
+        for i in 0..=8 {
+            let offset = MARGIN + i * CELL;
+            svg.push_str(&format!(
+                r#"<line x1="{offset}" y1="{MARGIN}" x2="{offset}" y2="{}" stroke="{grid}"/><line x1="{MARGIN}" y1="{offset}" x2="{}" y2="{offset}" stroke="{grid}"/>"#,
+                MARGIN + board,
+                MARGIN + board,
+                grid = hex(GRID)
+            ));
+        }
+        for i in 0..8 {
+            let offset = MARGIN + i * CELL + CELL / 2;
+            let (file, rank) = ((b'a' + i as u8) as char, 8 - i);
+            svg.push_str(&format!(
+                r#"<text x="{offset}" y="{}" text-anchor="middle" font-size="14" fill="{text}">{file}</text><text x="{}" y="{}" text-anchor="middle" font-size="14" fill="{text}">{rank}</text>"#,
+                MARGIN - 10,
+                MARGIN / 2,
+                offset + 5,
+                text = hex(TEXT)
+            ));
+        }
+
+        for pos in self.node.valid_movs.iter().filter(|&&m| m < PASS) {
+            let (x, y) = Self::center(*pos);
+            svg.push_str(&format!(
+                r#"<circle cx="{x}" cy="{y}" r="{}" fill="{}" fill-opacity="0.6"/>"#,
+                CELL / 8,
+                hex(VALID_MOVE)
+            ));
+        }
+        for pos in 0..64 {
+            let Some(side) = self.node.state.side_at(pos) else {
+                continue;
+            };
+            let (x, y) = Self::center(pos);
+            let fill = match side {
+                Side::Black => BLACK,
+                Side::White => WHITE,
+            };
+            svg.push_str(&format!(
+                r#"<circle cx="{x}" cy="{y}" r="{}" fill="{}"/>"#,
+                CELL * 2 / 5,
+                hex(fill)
+            ));
+        }
+        if let Some(pos) = self.last_move {
+            let (x, y) = Self::center(pos);
+            svg.push_str(&format!(
+                r#"<circle cx="{x}" cy="{y}" r="{}" fill="{}"/>"#,
+                CELL / 10,
+                hex(LAST_MOVE)
+            ));
+        }
+
+        let mut votes = self.votes.iter().collect::<Vec<_>>();
+        votes.sort();
+        for (pos, count) in votes.into_iter().filter(|(m, _)| **m < PASS) {
+            let (x, y) = Self::center(*pos);
+            svg.push_str(&format!(
+                r#"<text x="{x}" y="{}" text-anchor="middle" font-size="18" font-weight="bold" fill="{}">{count}</text>"#,
+                y + 6,
+                hex(VOTES)
+            ));
+        }
+
+        svg.push_str("</svg>");
+        svg
+    }
+
+    pub fn png(&self) -> Vec<u8> {
+        let mut canvas = Canvas::new(SIZE, SIZE, BACKGROUND);
+        canvas.fill_rect(MARGIN, MARGIN, CELL * 8, CELL * 8, BOARD);
+        for i in 0..=8 {
+            let offset = MARGIN + i * CELL;
+            canvas.fill_rect(offset, MARGIN, 1, CELL * 8, GRID);
+            canvas.fill_rect(MARGIN, offset, CELL * 8, 1, GRID);
+        }
+        for i in 0..8 {
+            let offset = MARGIN + i * CELL + CELL / 2;
+            canvas.draw_text(
+                offset - 3,
+                MARGIN - 15,
+                &((b'a' + i as u8) as char).to_string(),
+                2,
+                TEXT,
+            );
+            canvas.draw_text(MARGIN / 2 - 3, offset - 5, &(8 - i).to_string(), 2, TEXT);
+        }
+
+        for pos in self.node.valid_movs.iter().filter(|&&m| m < PASS) {
+            let (x, y) = Self::center(*pos);
+            canvas.fill_circle(x, y, CELL / 8, VALID_MOVE);
+        }
+        for pos in 0..64 {
+            let Some(side) = self.node.state.side_at(pos) else {
+                continue;
+            };
+            let (x, y) = Self::center(pos);
+            let fill = match side {
+                Side::Black => BLACK,
+                Side::White => WHITE,
+            };
+            canvas.fill_circle(x, y, CELL * 2 / 5, fill);
+        }
+        if let Some(pos) = self.last_move {
+            let (x, y) = Self::center(pos);
+            canvas.fill_circle(x, y, CELL / 10, LAST_MOVE);
+        }
+        for (pos, count) in self.votes.iter().filter(|(m, _)| **m < PASS) {
+            let (x, y) = Self::center(*pos);
+            let count = count.to_string();
+            let width = count.len() as u32 * 12;
+            canvas.draw_text(x - width / 2, y - 7, &count, 3, VOTES);
+        }
+
+        canvas.encode_png()
+    }
+}
+
+impl<N: Network> Mori<N> {
+    /// The view of a live node, its last move is recovered from the parent board.
+    pub fn board_view(&self, node_id: u128) -> Result<Option<BoardView>> {
+        let Some(node) = self.mori_nodes.get(&node_id)? else {
+            return Ok(None);
+        };
+        let last_move = match node.is_root() {
+            true => None,
+            false => match self.mori_nodes.get(&node.from.node_id)? {
+                Some(parent) => line_transcript(&[parent, node.clone()])
+                    .ok()
+                    .and_then(|movs| movs.into_iter().rev().find(|&m| m != PASS)),
+                None => Some(node.from.mov),
+            },
+        };
+        Ok(Some(BoardView::new(node, last_move)))
+    }
+}
+
+/// A tiny RGB raster, enough to draw a board without a browser.
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, fill: [u8; 3]) -> Self {
+        let pixels = fill
+            .iter()
+            .copied()
+            .cycle()
+            .take((width * height * 3) as usize)
+            .collect();
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn set(&mut self, x: u32, y: u32, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let i = ((y * self.width + x) * 3) as usize;
+            self.pixels[i..i + 3].copy_from_slice(&color);
+        }
+    }
+
+    fn fill_rect(&mut self, x: u32, y: u32, w: u32, h: u32, color: [u8; 3]) {
+        for py in y..y + h {
+            for px in x..x + w {
+                self.set(px, py, color);
+            }
+        }
+    }
+
+    fn fill_circle(&mut self, cx: u32, cy: u32, r: u32, color: [u8; 3]) {
+        let r2 = (r * r) as i64;
+        for py in cy.saturating_sub(r)..=cy + r {
+            for px in cx.saturating_sub(r)..=cx + r {
+                let (dx, dy) = (px as i64 - cx as i64, py as i64 - cy as i64);
+                if dx * dx + dy * dy <= r2 {
+                    self.set(px, py, color);
+                }
+            }
+        }
+    }
+
+    /// Draws digits and the files a-h with a 3x5 bitmap font.
+    fn draw_text(&mut self, x: u32, y: u32, text: &str, scale: u32, color: [u8; 3]) {
+        for (i, c) in text.chars().enumerate() {
+            let Some(glyph) = glyph(c) else {
+                continue;
+            };
+            let gx = x + i as u32 * 4 * scale;
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) != 0 {
+                        self.fill_rect(
+                            gx + col * scale,
+                            y + row as u32 * scale,
+                            scale,
+                            scale,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn encode_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(((self.width * 3 + 1) * self.height) as usize);
+        for row in self.pixels.chunks((self.width * 3) as usize) {
+            raw.push(0); // no filter
+            raw.extend_from_slice(row);
+        }
+
+        let mut ihdr = vec![];
+        ihdr.extend_from_slice(&self.width.to_be_bytes());
+        ihdr.extend_from_slice(&self.height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bit RGB
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &ihdr);
+        let mut idat = ZlibEncoder::new(Vec::new(), Compression::default());
+        idat.write_all(&raw).expect("writing to a vec cannot fail");
+        let idat = idat.finish().expect("writing to a vec cannot fail");
+        png_chunk(&mut png, b"IDAT", &idat);
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn glyph(c: char) -> Option<[u8; 5]> {
+    let glyph = match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        _ => return None,
+    };
+    Some(glyph)
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+#[test]
+fn test_render_board() {
+    use crate::cores::{GameState, NodeEdge, Vote};
+
+    let node = GameNode {
+        node_id: 7,
+        state: GameState::zero(),
+        from: NodeEdge { node_id: 0, mov: 0 },
+        game_status: 0,
+        valid_movs: vec![20, 29, 34, 43],
+        votes: vec![Vote {
+            sender: "aleo1voter".into(),
+            node_id: 7,
+            mov: 29,
+        }],
+    };
+    let view = BoardView::new(node, Some(27));
+
+    let svg = view.svg();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.ends_with("</svg>"));
+    assert_eq!(svg.matches(r##"fill="#111111""##).count(), 2);
+    assert_eq!(svg.matches(r#"fill-opacity="0.6""#).count(), 4);
+
+    let png = view.png();
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    assert!(png.ends_with(&[0xae, 0x42, 0x60, 0x82])); // IEND crc
+
+    // walk the chunks back and inflate the pixels
+    let (mut rest, mut idat) = (&png[8..], vec![]);
+    let mut kinds = vec![];
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+        let mut crc = Crc::new();
+        crc.update(&rest[4..8 + len]);
+        assert_eq!(rest[8 + len..12 + len], crc.sum().to_be_bytes());
+        if kind == b"IDAT" {
+            idat.extend_from_slice(data);
+        }
+        kinds.push(String::from_utf8(kind.to_vec()).unwrap());
+        rest = &rest[12 + len..];
+    }
+    assert_eq!(kinds, vec!["IHDR", "IDAT", "IEND"]);
+
+    let mut raw = vec![];
+    std::io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(&idat[..]), &mut raw).unwrap();
+    assert_eq!(raw.len(), ((SIZE * 3 + 1) * SIZE) as usize);
+    let pixel = |x: u32, y: u32| {
+        let i = (y * (SIZE * 3 + 1) + 1 + x * 3) as usize;
+        [raw[i], raw[i + 1], raw[i + 2]]
+    };
+    assert_eq!(pixel(0, 0), BACKGROUND);
+    let (x, y) = BoardView::center(28);
+    let disc = match view.node.state.side_at(28) {
+        Some(Side::Black) => BLACK,
+        Some(Side::White) => WHITE,
+        None => unreachable!("the opening board has a disc on 28"),
+    };
+    assert_eq!(pixel(x + CELL / 4, y), disc);
+}
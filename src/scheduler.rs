@@ -0,0 +1,528 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    time::Duration,
+};
+
+use aleo_rust::Network;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{MoriError, Result},
+    prune::unix_now,
+    Execution, Mori,
+};
+
+/// Seconds an open request counts as pending while its game is not indexed,
+/// after that it is assumed lost and the game is requested again.
+const PENDING_OPEN_SECS: i64 = 600;
+
+/// A cron-like schedule: five UTC fields `minute hour day month weekday`.
+///
+/// Fields take `*`, a value, a range `a-b`, a step `*/n` or `a-b/n` and comma
+/// separated lists of those. `@hourly`, `@daily`, `@weekly` and `@monthly`
+/// are accepted too.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // as in cron, when both days and weekdays are restricted either one matches
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    /// The first matching minute strictly after `after`, in unix seconds.
+    /// `None` if nothing matches within four years, like `0 0 31 2 *`.
+    pub fn next_after(&self, after: i64) -> Option<i64> {
+        let limit = after + 4 * 366 * 86400;
+        let mut t = (after.div_euclid(60) + 1) * 60;
+        while t < limit {
+            let days = t.div_euclid(86400);
+            if !self.day_matches(days) {
+                t = (days + 1) * 86400;
+                continue;
+            }
+            let minute = t.rem_euclid(86400) / 60;
+            if bit(self.hours, minute / 60) && bit(self.minutes, minute % 60) {
+                return Some(t);
+            }
+            t += 60;
+        }
+        None
+    }
+
+    fn day_matches(&self, days: i64) -> bool {
+        let (month, day) = civil_from_days(days);
+        if !bit(self.months, month) {
+            return false;
+        }
+        // 1970-01-01 was a Thursday
+        let weekday = bit(self.weekdays, (days + 4).rem_euclid(7));
+        let day = bit(self.days, day);
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = MoriError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let expanded = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let invalid = || MoriError::validation(format!("invalid schedule {s}"));
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        let &[minutes, hours, days, months, weekdays] = fields.as_slice() else {
+            return Err(invalid());
+        };
+        let weekday_set = parse_field(weekdays, 0, 7).ok_or_else(invalid)?;
+        Ok(CronSchedule {
+            source: s.trim().to_string(),
+            minutes: parse_field(minutes, 0, 59).ok_or_else(invalid)?,
+            hours: parse_field(hours, 0, 23).ok_or_else(invalid)?,
+            days: parse_field(days, 1, 31).ok_or_else(invalid)?,
+            months: parse_field(months, 1, 12).ok_or_else(invalid)?,
+            // 7 is Sunday too
+            weekdays: (weekday_set | weekday_set >> 7) & 0x7f,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = MoriError;
+
+    fn try_from(s: String) -> Result<Self> {
+        CronSchedule::from_str(&s)
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(schedule: CronSchedule) -> Self {
+        schedule.source
+    }
+}
+
+fn bit(set: u64, value: i64) -> bool {
+    set & (1 << value) != 0
+}
+
+/// The values of one cron field as a bit set, `None` if it does not parse.
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>().ok()?)),
+            None => (part, None),
+        };
+        let (lo, hi) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((lo, hi)) => (lo.parse().ok()?, hi.parse().ok()?),
+            // `a/n` runs from `a` to the end of the field
+            None if step.is_some() => (range.parse().ok()?, max),
+            None => {
+                let value = range.parse().ok()?;
+                (value, value)
+            }
+        };
+        if lo < min || hi > max || lo > hi || step == Some(0) {
+            return None;
+        }
+        for value in (lo..=hi).step_by(step.unwrap_or(1) as usize) {
+            set |= 1 << value;
+        }
+    }
+    Some(set)
+}
+
+/// Month and day of the month of a day since the unix epoch.
+fn civil_from_days(days: i64) -> (i64, i64) {
+    let z = days + 719468;
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (month, day)
+}
+
+/// When the scheduler opens games. It is off when there is no target and no
+/// schedule.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchedulerPolicy {
+    /// Number of active games kept open.
+    pub target_games: usize,
+    /// A game nobody voted on for this many seconds no longer counts as
+    /// active, a new one is opened next to it.
+    pub idle_secs: Option<u64>,
+    /// Open one more game each time a schedule fires, on top of the target.
+    pub schedules: Vec<CronSchedule>,
+    /// Do not open games while the fee records hold fewer microcredits.
+    pub min_fee_balance: u64,
+    /// Seconds between two checks.
+    pub interval_secs: u64,
+}
+
+impl SchedulerPolicy {
+    pub fn enabled(&self) -> bool {
+        self.target_games > 0 || !self.schedules.is_empty()
+    }
+}
+
+/// A game with open nodes, grouped under its root.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LiveGame {
+    pub root_id: u128,
+    pub open_nodes: usize,
+    /// Block timestamp of the last vote or move, unknown for games indexed
+    /// before node births were recorded and never voted on since.
+    pub last_activity: Option<i64>,
+    pub idle: bool,
+}
+
+/// An open the scheduler requested, stored under the id of the request so a
+/// restart does not open the same games again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledOpen {
+    pub requested_at: i64,
+    /// The node the AI created for the request, set by the execution worker.
+    pub node_id: Option<u128>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchedulerState {
+    /// `None` while the scheduler is not running.
+    pub policy: Option<SchedulerPolicy>,
+    pub last_check: Option<i64>,
+    pub next_scheduled: Option<i64>,
+    pub games: Vec<LiveGame>,
+    /// Times the opens whose game is not indexed yet were requested.
+    pub pending_opens: Vec<i64>,
+    pub fee_balance: Option<u64>,
+    /// Totals since startup.
+    pub opened: u64,
+    pub skipped: u64,
+    pub last_skip: Option<String>,
+}
+
+impl<N: Network> Mori<N> {
+    /// Opens the games `policy` asks for at `now`, returns how many were
+    /// queued.
+    ///
+    /// Opens that were queued but whose game is not indexed yet count towards
+    /// the target, so a slow proof does not open the same game twice.
+    pub fn check_games(&self, policy: &SchedulerPolicy, now: i64) -> Result<usize> {
+        let games = self.live_games(policy.idle_secs, now)?;
+
+        // an open is settled once its node is indexed, or lost after a while
+        let mut pending = vec![];
+        for (request, open) in self.scheduled_opens.get_all()? {
+            let indexed = match open.node_id {
+                Some(node_id) => self.mori_nodes.contain(&node_id)?,
+                None => false,
+            };
+            if indexed || now - open.requested_at >= PENDING_OPEN_SECS {
+                self.scheduled_opens.remove(&request)?;
+            } else {
+                pending.push(open.requested_at);
+            }
+        }
+        pending.sort();
+
+        let wanted = {
+            let mut state = self.scheduler.lock().unwrap_or_else(|e| e.into_inner());
+            let since = state.last_check.replace(now);
+            state.pending_opens = pending;
+
+            let due = match since {
+                Some(since) => policy
+                    .schedules
+                    .iter()
+                    .filter(|s| s.next_after(since).is_some_and(|t| t <= now))
+                    .count(),
+                None => 0,
+            };
+            state.next_scheduled = policy
+                .schedules
+                .iter()
+                .filter_map(|s| s.next_after(now))
+                .min();
+
+            let active = games.iter().filter(|g| !g.idle).count();
+            state.games = games;
+            policy
+                .target_games
+                .saturating_sub(active + state.pending_opens.len())
+                + due
+        };
+        if wanted == 0 {
+            return Ok(0);
+        }
+
+        let balance = self.fee_balance(policy.min_fee_balance);
+        {
+            let mut state = self.scheduler.lock().unwrap_or_else(|e| e.into_inner());
+            state.fee_balance = balance;
+            if let Some(balance) = balance.filter(|b| *b < policy.min_fee_balance) {
+                let reason = format!(
+                    "fee balance {balance} is below {}, {wanted} games not opened",
+                    policy.min_fee_balance
+                );
+                tracing::warn!("{reason}");
+                state.skipped += wanted as u64;
+                state.last_skip = Some(reason);
+                return Ok(0);
+            }
+        }
+
+        // the channel may block, the state stays readable meanwhile
+        let mut queued = 0;
+        let sent = (0..wanted).try_for_each(|_| {
+            let request = rand::random::<u128>();
+            let open = ScheduledOpen {
+                requested_at: now,
+                node_id: None,
+            };
+            self.scheduled_opens.insert(&request, &open)?;
+            if let Err(e) = self.tx.blocking_send(Execution::OpenGame(Some(request))) {
+                self.scheduled_opens.remove(&request)?;
+                return Err(MoriError::execution(e));
+            }
+            queued += 1;
+            Ok::<_, MoriError>(())
+        });
+        let mut state = self.scheduler.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .pending_opens
+            .extend(std::iter::repeat(now).take(queued));
+        state.opened += queued as u64;
+        sent?;
+        tracing::info!("scheduler queued {wanted} new games");
+        Ok(wanted)
+    }
+
+    /// The balance of the fee records, refilled when it is below `min`.
+    /// `None` when there is no minimum to check.
+    fn fee_balance(&self, min: u64) -> Option<u64> {
+        if min == 0 {
+            return None;
+        }
+        if self.fees.balance() < min {
+            if let Err(e) = self.fees.refill(&self.aleo_client, &self.pk, &self.vk) {
+                tracing::warn!("scheduler refill fee records error: {:?}", e);
+            }
+        }
+        Some(self.fees.balance())
+    }
+
+    /// The games that still have open nodes, by root.
+    pub fn live_games(&self, idle_secs: Option<u64>, now: i64) -> Result<Vec<LiveGame>> {
+        let mut roots = HashMap::new();
+        let mut games: BTreeMap<u128, LiveGame> = BTreeMap::new();
+        for (node_id, node) in self.nodes_by_status(0)? {
+            // walk up to the root, or the oldest indexed ancestor
+            let mut path = vec![];
+            let mut cur = node;
+            let root_id = loop {
+                if let Some(root_id) = roots.get(&cur.node_id) {
+                    break *root_id;
+                }
+                path.push(cur.node_id);
+                if cur.is_root() {
+                    break cur.node_id;
+                }
+                match self.mori_nodes.get(&cur.from.node_id)? {
+                    Some(parent) => cur = parent,
+                    None => break cur.node_id,
+                }
+            };
+            for id in path {
+                roots.insert(id, root_id);
+            }
+
+            let game = games.entry(root_id).or_insert(LiveGame {
+                root_id,
+                open_nodes: 0,
+                last_activity: None,
+                idle: false,
+            });
+            game.open_nodes += 1;
+            // the closed nodes of a game are older than its open ones
+            let birth = self.node_births.get(&node_id)?.map(|b| b.timestamp);
+            let voted = self
+                .votes
+                .scan_prefix(&node_id)
+                .map(|entry| entry.map(|(_, v)| v.block_timestamp))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .max();
+            game.last_activity = game.last_activity.max(birth).max(voted);
+        }
+
+        let mut games = games.into_values().collect::<Vec<_>>();
+        for game in &mut games {
+            game.idle = match (idle_secs, game.last_activity) {
+                (Some(idle), Some(last)) => now.saturating_sub(last) >= idle as i64,
+                _ => false,
+            };
+        }
+        Ok(games)
+    }
+
+    pub fn scheduler_state(&self) -> SchedulerState {
+        self.scheduler
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Checks the games every `policy.interval_secs` on a background thread,
+    /// once sync caught up: before that the live games are not all indexed.
+    pub fn schedule_games(&self, policy: SchedulerPolicy) {
+        if !policy.enabled() || policy.interval_secs == 0 {
+            return;
+        }
+        self.scheduler
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .policy = Some(policy.clone());
+        let self_clone = self.clone();
+        std::thread::spawn(move || loop {
+            if !self_clone.is_synced() {
+                tracing::debug!("scheduler waits for sync");
+            } else if let Err(e) = self_clone.check_games(&policy, unix_now()) {
+                tracing::error!("scheduler error: {:?}", e);
+            }
+            std::thread::sleep(Duration::from_secs(policy.interval_secs));
+        });
+    }
+}
+
+#[test]
+fn test_cron_schedule() {
+    let next = |s: &str, after| CronSchedule::from_str(s).unwrap().next_after(after);
+    assert_eq!(next("0 12 * * *", 0), Some(12 * 3600));
+    assert_eq!(next("*/15 * * * *", 60), Some(900));
+    assert_eq!(next("@daily", 0), Some(86400));
+    // 1970-01-01 was a Thursday, the next Sunday is the 4th
+    assert_eq!(next("@weekly", 0), Some(3 * 86400));
+    assert_eq!(next("0 0 * * 7", 0), Some(3 * 86400));
+    assert_eq!(next("@monthly", 0), Some(31 * 86400));
+    // the 10th, or any Saturday
+    assert_eq!(next("0 0 10 * 6", 0), Some(2 * 86400));
+    // the first weekday of March 1970 was Monday the 2nd
+    assert_eq!(
+        next("30 8-9/1 * 3 1-5", 0),
+        Some(60 * 86400 + 8 * 3600 + 1800)
+    );
+    assert_eq!(next("0 0 31 2 *", 0), None);
+
+    for invalid in [
+        "61 * * * *",
+        "* * *",
+        "*/0 * * * *",
+        "5-1 * * * *",
+        "@yearly",
+    ] {
+        assert!(CronSchedule::from_str(invalid).is_err(), "{invalid}");
+    }
+    let schedule: CronSchedule = serde_json::from_str("\"@daily\"").unwrap();
+    assert_eq!(serde_json::to_string(&schedule).unwrap(), "\"@daily\"");
+}
+
+#[test]
+fn test_check_games() {
+    use crate::{filter::BlockMeta, test_node};
+
+    let (mori, mut rx) = Mori::<aleo_rust::Testnet3>::test_open().unwrap();
+    let open = |node_id, parent, born| {
+        mori.mori_nodes
+            .insert(&node_id, &test_node(node_id, parent))
+            .unwrap();
+        let birth = BlockMeta {
+            height: 1,
+            timestamp: born,
+        };
+        mori.node_births.insert(&node_id, &birth).unwrap();
+    };
+    // the ids of the open requests sent to the workers
+    let queued = |rx: &mut tokio::sync::mpsc::Receiver<Execution>| {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|exec| match exec {
+                Execution::OpenGame(Some(request)) => request,
+                other => panic!("unexpected execution {other:?}"),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // game 1 moved at 2500, game 2 was opened at 0
+    open(1, 0, 0);
+    open(11, 1, 2500);
+    open(2, 0, 0);
+    let policy = SchedulerPolicy {
+        target_games: 3,
+        idle_secs: Some(3000),
+        schedules: vec![CronSchedule::from_str("@hourly").unwrap()],
+        min_fee_balance: 0,
+        interval_secs: 60,
+    };
+    let games = mori.live_games(policy.idle_secs, 3300).unwrap();
+    assert_eq!(
+        games
+            .iter()
+            .map(|g| (g.root_id, g.open_nodes, g.idle))
+            .collect::<Vec<_>>(),
+        vec![(1, 2, false), (2, 1, true)]
+    );
+
+    // the idle game does not count
+    assert_eq!(mori.check_games(&policy, 3300).unwrap(), 2);
+    let requests = queued(&mut rx);
+    assert_eq!(requests.len(), 2);
+    // still pending, also after a restart
+    *mori.scheduler.lock().unwrap() = SchedulerState::default();
+    assert_eq!(mori.check_games(&policy, 3360).unwrap(), 0);
+
+    // one of them shows up, a game opened by hand does not settle the other,
+    // and the hour strikes
+    mori.scheduled_opens
+        .update(&requests[0], |open| {
+            open.as_mut().unwrap().node_id = Some(3)
+        })
+        .unwrap();
+    open(3, 0, 3400);
+    open(4, 0, 3400);
+    assert_eq!(mori.check_games(&policy, 3600).unwrap(), 1);
+    assert_eq!(queued(&mut rx).len(), 1);
+    let state = mori.scheduler_state();
+    assert_eq!(state.pending_opens, vec![3300, 3600]);
+    assert_eq!(state.next_scheduled, Some(7200));
+    assert_eq!(state.opened, 1);
+    assert!(!mori.scheduled_opens.contain(&requests[0]).unwrap());
+    assert!(mori.scheduled_opens.contain(&requests[1]).unwrap());
+
+    // the opens that never showed up expire, and are requested again once
+    // the target is short
+    let later = 3600 + PENDING_OPEN_SECS;
+    assert_eq!(mori.check_games(&policy, later).unwrap(), 0);
+    assert!(mori.scheduler_state().pending_opens.is_empty());
+    mori.mori_nodes.remove(&4).unwrap();
+    assert_eq!(mori.check_games(&policy, later).unwrap(), 1);
+    assert_eq!(queued(&mut rx).len(), 1);
+    assert_eq!(mori.scheduler_state().skipped, 0);
+}
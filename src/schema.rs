@@ -0,0 +1,179 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    cores::{GameNode, Rejection, SkippedVote, VoteRecord},
+    db::{DBMap, KEY_LAYOUT},
+    error::{MoriError, Result},
+    filter::BlockMeta,
+    games::FinishedGame,
+    mirror::MirroredNode,
+    players::PlayerStats,
+    scheduler::ScheduledOpen,
+    store::WriteBatch,
+};
+
+/// First byte of every stored value, followed by the little-endian version.
+const ENVELOPE_TAG: u8 = 0xfe;
+const ENVELOPE_LEN: usize = 3;
+
+/// A value stored in a `DBMap`.
+///
+/// Bump `VERSION` whenever the serialized layout changes and decode the older
+/// layouts in `upgrade`. Values written before versioning are version 0.
+pub trait Versioned: Serialize + DeserializeOwned {
+    const VERSION: u16 = 0;
+
+    /// Decodes a value written at an older `version`.
+    fn upgrade(version: u16, _bytes: &[u8]) -> Result<Self> {
+        Err(MoriError::Storage(format!(
+            "no upgrade from version {version} to {}",
+            Self::VERSION
+        )))
+    }
+}
+
+impl Versioned for u8 {}
+impl Versioned for u16 {}
+impl Versioned for u32 {}
+impl Versioned for i64 {}
+impl Versioned for String {}
+impl Versioned for Vec<u128> {}
+impl Versioned for GameNode {}
+impl Versioned for VoteRecord {}
+impl Versioned for SkippedVote {}
+impl Versioned for PlayerStats {}
+impl Versioned for FinishedGame {}
+impl Versioned for Rejection {}
+impl Versioned for MirroredNode {}
+impl Versioned for BlockMeta {}
+impl Versioned for ScheduledOpen {}
+
+pub fn encode<V: Versioned>(value: &V) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(ENVELOPE_LEN);
+    bytes.push(ENVELOPE_TAG);
+    bytes.extend_from_slice(&V::VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, value)?;
+    Ok(bytes)
+}
+
+/// The version and the payload of an enveloped value.
+pub fn open_envelope(bytes: &[u8]) -> Result<(u16, &[u8])> {
+    match bytes {
+        [ENVELOPE_TAG, lo, hi, payload @ ..] => Ok((u16::from_le_bytes([*lo, *hi]), payload)),
+        _ => Err(MoriError::Storage(
+            "value has no version envelope, run the migrations first".to_string(),
+        )),
+    }
+}
+
+pub fn decode<V: Versioned>(bytes: &[u8]) -> Result<V> {
+    let (version, payload) = open_envelope(bytes)?;
+    decode_version(version, payload)
+}
+
+/// Decodes a payload written at `version`, upgrading it when it is older.
+pub fn decode_version<V: Versioned>(version: u16, payload: &[u8]) -> Result<V> {
+    match version.cmp(&V::VERSION) {
+        std::cmp::Ordering::Equal => Ok(bincode::deserialize(payload)?),
+        std::cmp::Ordering::Less => V::upgrade(version, payload),
+        std::cmp::Ordering::Greater => Err(MoriError::Storage(format!(
+            "value version {version} is newer than {}",
+            V::VERSION
+        ))),
+    }
+}
+
+/// A table whose stored values can be brought to the current version.
+pub trait Migrate {
+    fn table(&self) -> String;
+
+    fn version(&self) -> u16;
+
+    /// Adds the rewrites of the values older than `version` to `batch`,
+    /// `from` is `None` for tables written before versioning. Returns the
+    /// number of rewritten values.
+    fn migrate(&self, from: Option<u16>, batch: &mut WriteBatch) -> Result<usize>;
+
+    /// Adds the re-encoding of the keys written with key layout `from` to
+    /// `batch`, returns the number of moved entries.
+    fn migrate_keys(&self, from: u16, batch: &mut WriteBatch) -> Result<usize>;
+
+    /// Names of the secondary indexes of the table.
+    fn indexes(&self) -> Vec<String>;
+
+    /// Rebuilds an index from the entries, returns the number of index entries.
+    fn rebuild_index(&self, index: &str) -> Result<usize>;
+}
+
+/// The tables to migrate on startup and the schema version each was left at.
+pub struct Migrations<'a> {
+    versions: DBMap<String, u16>,
+    tables: Vec<&'a dyn Migrate>,
+}
+
+impl<'a> Migrations<'a> {
+    pub fn new(versions: DBMap<String, u16>) -> Self {
+        Self {
+            versions,
+            tables: vec![],
+        }
+    }
+
+    pub fn register(mut self, table: &'a dyn Migrate) -> Self {
+        self.tables.push(table);
+        self
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let moved = self.versions.migrate_version_keys()?;
+        if moved > 0 {
+            tracing::info!("re-encoded {moved} schema version keys");
+        }
+
+        for table in &self.tables {
+            let name = table.table();
+            let keys = format!("{name}/keys");
+            let from = self.versions.get(&keys)?.unwrap_or(0);
+            // the data and its version are written together, a crash in
+            // between must not run a migration twice
+            if from < KEY_LAYOUT {
+                let mut batch = WriteBatch::default();
+                let moved = table.migrate_keys(from, &mut batch)?;
+                self.versions.write_with(batch, &keys, &KEY_LAYOUT)?;
+                tracing::info!(
+                    "migrated keys of table {name} from layout {from} to {KEY_LAYOUT}, {moved} entries moved"
+                );
+            }
+
+            let version = table.version();
+            let from = self.versions.get(&name)?;
+            match from {
+                Some(from) if from == version => {}
+                Some(from) if from > version => {
+                    return Err(MoriError::Storage(format!(
+                        "table {name} is at version {from}, newer than {version}"
+                    )))
+                }
+                _ => {
+                    let mut batch = WriteBatch::default();
+                    let rewritten = table.migrate(from, &mut batch)?;
+                    self.versions.write_with(batch, &name, &version)?;
+                    tracing::info!(
+                        "migrated table {name} from {from:?} to {version}, {rewritten} values rewritten"
+                    );
+                }
+            }
+
+            // an index is built once, when it is first declared
+            for index in table.indexes() {
+                if self.versions.contain(&index)? {
+                    continue;
+                }
+                let built = table.rebuild_index(&index)?;
+                self.versions.insert(&index, &1)?;
+                tracing::info!("built index {index} with {built} entries");
+            }
+        }
+        Ok(())
+    }
+}
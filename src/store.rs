@@ -0,0 +1,281 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Bound,
+    path::Path,
+    str::FromStr,
+    sync::RwLock,
+};
+
+use crate::error::{MoriError, Result};
+
+/// Entries a `MemoryStore` iterator copies out per lock.
+const MEMORY_CHUNK: usize = 256;
+
+pub type RawEntry = (Box<[u8]>, Box<[u8]>);
+pub type RawIter<'a> = Box<dyn Iterator<Item = Result<RawEntry>> + 'a>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Where an iteration starts, as `rocksdb::IteratorMode`.
+#[derive(Debug, Clone, Copy)]
+pub enum IteratorMode<'a> {
+    Start,
+    End,
+    /// From the first key at or after `key`, at or before it when reversed.
+    From(&'a [u8], Direction),
+}
+
+/// Writes applied in order and atomically by `KvStore::write`.
+#[derive(Debug, Default)]
+pub struct WriteBatch(Vec<(Vec<u8>, Option<Vec<u8>>)>);
+
+impl WriteBatch {
+    pub fn put(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) {
+        self.0
+            .push((key.as_ref().to_vec(), Some(value.as_ref().to_vec())));
+    }
+
+    pub fn delete(&mut self, key: impl AsRef<[u8]>) {
+        self.0.push((key.as_ref().to_vec(), None));
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// An ordered byte key-value store, the storage under every `DBMap`.
+pub trait KvStore: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    fn write(&self, batch: WriteBatch) -> Result<()>;
+
+    /// Entries in key order from `mode` on, until the end of the store.
+    fn iterator(&self, mode: IteratorMode) -> RawIter<'_>;
+
+    /// Writes a consistent copy of the store to `path`, which must not exist yet.
+    fn checkpoint(&self, path: &Path) -> Result<()>;
+
+    /// Whether `checkpoint` can succeed at all.
+    fn can_checkpoint(&self) -> bool {
+        true
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.put(key, value);
+        self.write(batch)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    RocksDB,
+    Memory,
+}
+
+impl FromStr for StorageKind {
+    type Err = MoriError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rocksdb" => Ok(StorageKind::RocksDB),
+            "memory" => Ok(StorageKind::Memory),
+            _ => Err(MoriError::validation(format!("unknown storage {s}"))),
+        }
+    }
+}
+
+pub struct RocksStore(rocksdb::DB);
+
+impl RocksStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        // Customize database options.
+        let mut options = rocksdb::Options::default();
+        options.set_compression_type(rocksdb::DBCompressionType::Lz4);
+        options.increase_parallelism(2);
+        options.create_if_missing(true);
+
+        Ok(RocksStore(rocksdb::DB::open(&options, path)?))
+    }
+}
+
+impl KvStore for RocksStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.get(key)?)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut rocks_batch = rocksdb::WriteBatch::default();
+        for (key, value) in batch.0 {
+            match value {
+                Some(value) => rocks_batch.put(key, value),
+                None => rocks_batch.delete(key),
+            }
+        }
+        self.0.write(rocks_batch)?;
+        Ok(())
+    }
+
+    fn iterator(&self, mode: IteratorMode) -> RawIter<'_> {
+        let mode = match mode {
+            IteratorMode::Start => rocksdb::IteratorMode::Start,
+            IteratorMode::End => rocksdb::IteratorMode::End,
+            IteratorMode::From(key, Direction::Forward) => {
+                rocksdb::IteratorMode::From(key, rocksdb::Direction::Forward)
+            }
+            IteratorMode::From(key, Direction::Reverse) => {
+                rocksdb::IteratorMode::From(key, rocksdb::Direction::Reverse)
+            }
+        };
+        Box::new(
+            self.0
+                .iterator(mode)
+                .map(|item| item.map_err(MoriError::from)),
+        )
+    }
+
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        rocksdb::checkpoint::Checkpoint::new(&self.0)?.create_checkpoint(path)?;
+        Ok(())
+    }
+}
+
+/// A store kept in memory and lost on exit, for demos and tests.
+#[derive(Default)]
+pub struct MemoryStore(RwLock<BTreeMap<Vec<u8>, Vec<u8>>>);
+
+impl KvStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let entries = self.0.read().unwrap_or_else(|e| e.into_inner());
+        Ok(entries.get(key).cloned())
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut entries = self.0.write().unwrap_or_else(|e| e.into_inner());
+        for (key, value) in batch.0 {
+            match value {
+                Some(value) => entries.insert(key, value),
+                None => entries.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn iterator(&self, mode: IteratorMode) -> RawIter<'_> {
+        let (next, direction) = match mode {
+            IteratorMode::Start => (Bound::Unbounded, Direction::Forward),
+            IteratorMode::End => (Bound::Unbounded, Direction::Reverse),
+            IteratorMode::From(key, direction) => (Bound::Included(key.to_vec()), direction),
+        };
+        Box::new(MemoryIter {
+            store: self,
+            next,
+            direction,
+            chunk: VecDeque::new(),
+        })
+    }
+
+    fn checkpoint(&self, _path: &Path) -> Result<()> {
+        Err(MoriError::Storage(
+            "the memory storage has no checkpoints".to_string(),
+        ))
+    }
+
+    fn can_checkpoint(&self) -> bool {
+        false
+    }
+}
+
+/// Iterates a `MemoryStore` a chunk at a time, so writers are not blocked
+/// while the entries are consumed.
+struct MemoryIter<'a> {
+    store: &'a MemoryStore,
+    /// Bound of the next chunk, past the last entry of the current one.
+    next: Bound<Vec<u8>>,
+    direction: Direction,
+    chunk: VecDeque<RawEntry>,
+}
+
+impl MemoryIter<'_> {
+    fn fill(&mut self) {
+        let chunk = {
+            let entries = self.store.0.read().unwrap_or_else(|e| e.into_inner());
+            let next = match &self.next {
+                Bound::Included(key) => Bound::Included(key.as_slice()),
+                Bound::Excluded(key) => Bound::Excluded(key.as_slice()),
+                Bound::Unbounded => Bound::Unbounded,
+            };
+            let range: Box<dyn Iterator<Item = (&Vec<u8>, &Vec<u8>)>> = match self.direction {
+                Direction::Forward => Box::new(entries.range::<[u8], _>((next, Bound::Unbounded))),
+                Direction::Reverse => {
+                    Box::new(entries.range::<[u8], _>((Bound::Unbounded, next)).rev())
+                }
+            };
+            let chunk = range
+                .take(MEMORY_CHUNK)
+                .map(|(key, value)| (key.clone().into(), value.clone().into()))
+                .collect::<VecDeque<RawEntry>>();
+            chunk
+        };
+        if let Some((key, _)) = chunk.back() {
+            self.next = Bound::Excluded(key.to_vec());
+        }
+        self.chunk = chunk;
+    }
+}
+
+impl Iterator for MemoryIter<'_> {
+    type Item = Result<RawEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.chunk.is_empty() {
+            self.fill();
+        }
+        self.chunk.pop_front().map(Ok)
+    }
+}
+
+#[test]
+fn test_memory_store() {
+    let store = MemoryStore::default();
+    let mut batch = WriteBatch::default();
+    for i in 0..600u32 {
+        batch.put(i.to_be_bytes(), i.to_le_bytes());
+    }
+    batch.delete(7u32.to_be_bytes());
+    store.write(batch).unwrap();
+    assert_eq!(store.get(&7u32.to_be_bytes()).unwrap(), None);
+    assert_eq!(
+        store.get(&8u32.to_be_bytes()).unwrap(),
+        Some(8u32.to_le_bytes().to_vec())
+    );
+
+    let mid = 300u32.to_be_bytes();
+    let deleted = 7u32.to_be_bytes();
+    let keys = |mode| {
+        store
+            .iterator(mode)
+            .map(|item| u32::from_be_bytes(item.unwrap().0[..].try_into().unwrap()))
+            .collect::<Vec<_>>()
+    };
+    // longer than a chunk
+    assert_eq!(keys(IteratorMode::Start).len(), 599);
+    assert_eq!(keys(IteratorMode::End)[..2], [599, 598]);
+    assert_eq!(
+        keys(IteratorMode::From(&mid, Direction::Forward))[..2],
+        [300, 301]
+    );
+    assert_eq!(
+        keys(IteratorMode::From(&deleted, Direction::Reverse)),
+        [6, 5, 4, 3, 2, 1, 0]
+    );
+}
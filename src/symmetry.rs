@@ -0,0 +1,242 @@
+use aleo_rust::Network;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cores::{GameNode, GameState, Side, PASS},
+    db::DBTransaction,
+    error::Result,
+    Mori,
+};
+
+/// The 8 symmetries of the board.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Symmetry {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+    Transpose,
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipHorizontal,
+        Symmetry::FlipVertical,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    pub fn inverse(&self) -> Self {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            s => *s,
+        }
+    }
+
+    /// Maps a square index, a pass stays a pass.
+    pub fn square(&self, pos: u8) -> u8 {
+        if pos >= PASS {
+            return pos;
+        }
+        let (r, c) = (pos / 8, pos % 8);
+        let (r, c) = match self {
+            Symmetry::Identity => (r, c),
+            Symmetry::Rotate90 => (c, 7 - r),
+            Symmetry::Rotate180 => (7 - r, 7 - c),
+            Symmetry::Rotate270 => (7 - c, r),
+            Symmetry::FlipHorizontal => (r, 7 - c),
+            Symmetry::FlipVertical => (7 - r, c),
+            Symmetry::Transpose => (c, r),
+            Symmetry::AntiTranspose => (7 - c, 7 - r),
+        };
+        r * 8 + c
+    }
+
+    pub fn state(&self, state: &GameState) -> GameState {
+        state.permute(|pos| self.square(pos))
+    }
+}
+
+/// The smallest board of the 8 symmetric ones, and the symmetry that maps `state` onto it.
+pub fn canonical(state: &GameState) -> (GameState, Symmetry) {
+    Symmetry::ALL
+        .iter()
+        .map(|s| (s.state(state), *s))
+        .min_by_key(|(s, _)| s.raw())
+        .unwrap()
+}
+
+const fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+const ZOBRIST: [[u64; 2]; 64] = {
+    let mut table = [[0; 2]; 64];
+    let mut i = 0;
+    while i < 64 {
+        table[i][0] = splitmix64(2 * i as u64);
+        table[i][1] = splitmix64(2 * i as u64 + 1);
+        i += 1;
+    }
+    table
+};
+
+pub fn zobrist(state: &GameState) -> u64 {
+    (0..64).fold(0, |hash, pos| match state.side_at(pos) {
+        Some(Side::Black) => hash ^ ZOBRIST[pos as usize][0],
+        Some(Side::White) => hash ^ ZOBRIST[pos as usize][1],
+        None => hash,
+    })
+}
+
+/// Hash shared by all positions equal up to rotation or reflection.
+/// Nodes are always waiting on the voters, so the side to move is not hashed.
+pub fn position_hash(state: &GameState) -> u64 {
+    zobrist(&canonical(state).0)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Transposition {
+    pub node_id: u128,
+    pub game_id: u128,
+    pub symmetry: Symmetry,
+}
+
+impl<N: Network> Mori<N> {
+    pub fn index_position(&self, txn: &mut DBTransaction, node: &GameNode) -> Result<()> {
+        let hash = position_hash(&node.state);
+        txn.update(&self.positions, &hash, |nodes| {
+            let nodes = nodes.get_or_insert_with(Vec::new);
+            if !nodes.contains(&node.node_id) {
+                nodes.push(node.node_id);
+            }
+        })
+    }
+
+    /// Other nodes with the same position up to symmetry, and the symmetry
+    /// that maps their board onto `node`'s board.
+    pub fn transpositions(
+        &self,
+        txn: &mut DBTransaction,
+        node: &GameNode,
+    ) -> Result<Vec<(GameNode, Symmetry)>> {
+        let (canon, to_canon) = canonical(&node.state);
+        let ids = txn
+            .get(&self.positions, &zobrist(&canon))?
+            .unwrap_or_default();
+
+        let mut found = vec![];
+        for id in ids.into_iter().filter(|id| *id != node.node_id) {
+            let Some(other) = txn.get(&self.mori_nodes, &id)? else {
+                continue;
+            };
+            let (other_canon, other_to_canon) = canonical(&other.state);
+            // guard against hash collisions
+            if other_canon != canon {
+                continue;
+            }
+            let symmetry = compose(other_to_canon, to_canon.inverse());
+            found.push((other, symmetry));
+        }
+        Ok(found)
+    }
+
+    /// Valid moves of an already analysed transposition, mapped onto `node`.
+    pub fn transposed_valid_movs(
+        &self,
+        txn: &mut DBTransaction,
+        node: &GameNode,
+    ) -> Result<Option<Vec<u8>>> {
+        let found = self
+            .transpositions(txn, node)?
+            .into_iter()
+            .find(|(other, _)| !other.valid_movs.is_empty());
+        Ok(found.map(|(other, symmetry)| {
+            tracing::info!(
+                "node {} is a transposition of node {}, reuse its analysis",
+                node.node_id,
+                other.node_id
+            );
+            other
+                .valid_movs
+                .iter()
+                .map(|m| symmetry.square(*m))
+                .collect()
+        }))
+    }
+
+    pub fn get_transpositions(&self, node_id: u128) -> Result<Option<Vec<Transposition>>> {
+        let mut txn = self.transaction();
+        let Some(node) = txn.get(&self.mori_nodes, &node_id)? else {
+            return Ok(None);
+        };
+        let mut result = vec![];
+        for (other, symmetry) in self.transpositions(&mut txn, &node)? {
+            result.push(Transposition {
+                node_id: other.node_id,
+                game_id: self.game_root(&mut txn, other.node_id)?,
+                symmetry,
+            });
+        }
+        Ok(Some(result))
+    }
+}
+
+/// The symmetry applying `first` then `second`.
+pub fn compose(first: Symmetry, second: Symmetry) -> Symmetry {
+    // a symmetry is determined by where it maps two independent squares
+    let (a, b) = (
+        second.square(first.square(0)),
+        second.square(first.square(1)),
+    );
+    *Symmetry::ALL
+        .iter()
+        .find(|s| s.square(0) == a && s.square(1) == b)
+        .unwrap()
+}
+
+#[test]
+fn test_symmetries() {
+    for s in Symmetry::ALL {
+        for pos in 0..=PASS {
+            assert_eq!(s.inverse().square(s.square(pos)), pos);
+        }
+        for t in Symmetry::ALL {
+            let st = compose(s, t);
+            assert!((0..64).all(|p| st.square(p) == t.square(s.square(p))));
+        }
+    }
+
+    // the opening is symmetric under rotating by 180 degrees
+    let zero = GameState::zero();
+    assert_eq!(Symmetry::Rotate180.state(&zero), zero);
+    assert_ne!(Symmetry::Rotate90.state(&zero), zero);
+}
+
+#[test]
+fn test_position_hash_transpositions() {
+    let zero = GameState::zero();
+    let openings = zero
+        .legal_moves(Side::Black)
+        .into_iter()
+        .map(|m| zero.play(m, Side::Black).unwrap())
+        .collect::<Vec<_>>();
+
+    // the four first moves are all the same position up to symmetry
+    let hash = position_hash(&openings[0]);
+    assert!(openings.iter().all(|s| position_hash(s) == hash));
+    assert_ne!(zobrist(&openings[0]), zobrist(&openings[1]));
+    assert_ne!(hash, position_hash(&zero));
+}
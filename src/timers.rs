@@ -0,0 +1,370 @@
+use std::{str::FromStr, time::Duration};
+
+use aleo_rust::Network;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cores::{GameNode, MovRequest, PASS},
+    error::{MoriError, Result},
+    games::GameOutcome,
+    prune::unix_now,
+    Execution, Mori,
+};
+
+/// Seconds before the fallback of a node is applied again, when the move it
+/// sent never made it on chain.
+const FALLBACK_RETRY_SECS: i64 = 600;
+
+/// Positional value of each square for the `best_square` fallback: corners are worth
+/// the most, the squares next to them give a corner away.
+#[rustfmt::skip]
+const SQUARE_WEIGHTS: [i8; 64] = [
+    100, -20, 10,  5,  5, 10, -20, 100,
+    -20, -50, -2, -2, -2, -2, -50, -20,
+     10,  -2, -1, -1, -1, -1,  -2,  10,
+      5,  -2, -1, -1, -1, -1,  -2,   5,
+      5,  -2, -1, -1, -1, -1,  -2,   5,
+     10,  -2, -1, -1, -1, -1,  -2,  10,
+    -20, -50, -2, -2, -2, -2, -50, -20,
+    100, -20, 10,  5,  5, 10, -20, 100,
+];
+
+/// What is played for the voters when nobody voted on a node in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutFallback {
+    /// A random one of the node's valid moves.
+    Random,
+    /// The valid move on the best square of `SQUARE_WEIGHTS`, corners first.
+    BestSquare,
+    /// A pass when it is the voters' only move. Otherwise the voters forfeit
+    /// and the line is archived as a loss.
+    Pass,
+}
+
+impl FromStr for TimeoutFallback {
+    type Err = MoriError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "random" => Ok(TimeoutFallback::Random),
+            "best_square" => Ok(TimeoutFallback::BestSquare),
+            "pass" => Ok(TimeoutFallback::Pass),
+            _ => Err(MoriError::validation(format!(
+                "unknown timeout fallback {s}"
+            ))),
+        }
+    }
+}
+
+/// The move played for the voters of `node`, `None` when they forfeit. The
+/// valid moves come from the analysis of the node, so
+/// they are the moves of whoever is to play.
+fn fallback_move(fallback: TimeoutFallback, node: &GameNode) -> Option<u8> {
+    let movs = node
+        .valid_movs
+        .iter()
+        .copied()
+        .filter(|mov| *mov != PASS)
+        .collect::<Vec<_>>();
+    if movs.is_empty() {
+        return Some(PASS);
+    }
+    match fallback {
+        TimeoutFallback::Random => movs.choose(&mut rand::thread_rng()).copied(),
+        TimeoutFallback::BestSquare => movs.iter().copied().max_by_key(|mov| {
+            let weight = SQUARE_WEIGHTS.get(*mov as usize).copied();
+            (weight.unwrap_or(i8::MIN), std::cmp::Reverse(*mov))
+        }),
+        TimeoutFallback::Pass => None,
+    }
+}
+
+/// How long the voters have on a node, counted from the block that created
+/// it. The timers are off when neither deadline is set.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TurnTimerPolicy {
+    pub deadline_blocks: Option<u32>,
+    pub deadline_secs: Option<u64>,
+    pub fallback: TimeoutFallback,
+    /// Seconds between two checks.
+    pub interval_secs: u64,
+}
+
+impl TurnTimerPolicy {
+    pub fn enabled(&self) -> bool {
+        self.deadline_blocks.is_some() || self.deadline_secs.is_some()
+    }
+}
+
+/// The deadline of a node waiting for its first vote, whichever of the two
+/// passes first.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TurnDeadline {
+    pub node_id: u128,
+    pub height: Option<u32>,
+    pub timestamp: Option<i64>,
+    pub expired: bool,
+    /// When the fallback was played. It is played again after
+    /// `FALLBACK_RETRY_SECS` if the node is still waiting.
+    pub timed_out: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+pub struct TurnTimers {
+    policy: Option<TurnTimerPolicy>,
+}
+
+impl<N: Network> Mori<N> {
+    /// Applies the fallback of the nodes whose deadline passed at `now`,
+    /// returns their ids. Nodes whose fallback was played within
+    /// `FALLBACK_RETRY_SECS` are skipped, also across restarts.
+    pub fn check_turns(&self, policy: &TurnTimerPolicy, now: i64) -> Result<Vec<u128>> {
+        let height = self.network_height.get(&self.network_key)?.unwrap_or(0);
+        let mut expired = vec![];
+        for (node_id, node) in self.nodes_by_status(0)? {
+            match self.turn_deadline(policy, &node, height, now)? {
+                Some(deadline)
+                    if deadline.expired
+                        && !deadline
+                            .timed_out
+                            .is_some_and(|fired| now - fired < FALLBACK_RETRY_SECS) => {}
+                _ => continue,
+            }
+            self.timed_out_turns.insert(&node_id, &now)?;
+            tracing::info!(
+                "turn on node {node_id} timed out, playing {:?}",
+                policy.fallback
+            );
+            match self.play_fallback(policy.fallback, node) {
+                Ok(()) => expired.push(node_id),
+                Err(e) => tracing::error!("node {node_id} timeout fallback error: {:?}", e),
+            }
+        }
+        Ok(expired)
+    }
+
+    fn play_fallback(&self, fallback: TimeoutFallback, node: GameNode) -> Result<()> {
+        let Some(mov) = fallback_move(fallback, &node) else {
+            let mut txn = self.transaction();
+            // a vote that landed meanwhile fails the commit
+            if txn.get(&self.mori_nodes, &node.node_id)?.as_ref() != Some(&node) {
+                return Ok(());
+            }
+            let game = self.finalize_game(&mut txn, &node, Some(GameOutcome::WhiteWins))?;
+            txn.commit()?;
+            tracing::info!(
+                "voters of node {} forfeited game {}",
+                node.node_id,
+                game.root_id
+            );
+            return Ok(());
+        };
+        for mov in self.request_moves(MovRequest::unvoted(node.node_id, mov))? {
+            self.tx
+                .blocking_send(Execution::MoveToNext(mov))
+                .map_err(MoriError::execution)?;
+        }
+        Ok(())
+    }
+
+    /// The deadline of `node` if it is an open leaf nobody voted on. Nodes
+    /// without a recorded birth have none.
+    fn turn_deadline(
+        &self,
+        policy: &TurnTimerPolicy,
+        node: &GameNode,
+        height: u32,
+        now: i64,
+    ) -> Result<Option<TurnDeadline>> {
+        if node.game_status != 0 || !node.votes.is_empty() {
+            return Ok(None);
+        }
+        if !self
+            .mori_nodes
+            .query_keys(&self.children, &node.node_id)?
+            .is_empty()
+        {
+            return Ok(None);
+        }
+        let Some(birth) = self.node_births.get(&node.node_id)? else {
+            return Ok(None);
+        };
+        let by_height = policy
+            .deadline_blocks
+            .map(|blocks| birth.height.saturating_add(blocks));
+        let by_time = policy
+            .deadline_secs
+            .map(|secs| birth.timestamp.saturating_add(secs as i64));
+        Ok(Some(TurnDeadline {
+            node_id: node.node_id,
+            height: by_height,
+            timestamp: by_time,
+            expired: by_height.is_some_and(|h| height >= h) || by_time.is_some_and(|t| now >= t),
+            timed_out: self.timed_out_turns.get(&node.node_id)?,
+        }))
+    }
+
+    /// Whether the timers will still play for `node`: it has a deadline.
+    /// Prune leaves such nodes alone.
+    pub(crate) fn turn_pending(&self, node: &GameNode) -> Result<bool> {
+        let policy = self
+            .turn_timers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .policy;
+        let Some(policy) = policy else {
+            return Ok(false);
+        };
+        let deadline = self.turn_deadline(&policy, node, self.synced_height()?, unix_now())?;
+        Ok(deadline.is_some())
+    }
+
+    /// The deadlines of `nodes`, empty while the timers are off.
+    pub fn turn_deadlines(&self, nodes: &[(u128, GameNode)]) -> Result<Vec<TurnDeadline>> {
+        let policy = self
+            .turn_timers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .policy;
+        let Some(policy) = policy else {
+            return Ok(vec![]);
+        };
+        let height = self.network_height.get(&self.network_key)?.unwrap_or(0);
+        let now = unix_now();
+        let mut deadlines = vec![];
+        for (_, node) in nodes {
+            if let Some(deadline) = self.turn_deadline(&policy, node, height, now)? {
+                deadlines.push(deadline);
+            }
+        }
+        Ok(deadlines)
+    }
+
+    /// Checks the turns every `policy.interval_secs` on a background thread.
+    pub fn schedule_turn_timers(&self, policy: TurnTimerPolicy) {
+        if !policy.enabled() || policy.interval_secs == 0 {
+            return;
+        }
+        self.turn_timers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .policy = Some(policy);
+        let self_clone = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(policy.interval_secs));
+            if let Err(e) = self_clone.check_turns(&policy, unix_now()) {
+                tracing::error!("turn timers error: {:?}", e);
+            }
+        });
+    }
+}
+
+#[test]
+fn test_turn_timers() {
+    use crate::{cores::Vote, filter::BlockMeta, prune::PruneOptions, test_node};
+
+    let (mori, _rx) = Mori::<aleo_rust::Testnet3>::test_open().unwrap();
+    // 1 was voted on and led to 2, 3 is fresh, 4 has no birth
+    for (id, parent, born) in [(1, 0, 10), (2, 1, 20), (3, 0, 95)] {
+        mori.mori_nodes.insert(&id, &test_node(id, parent)).unwrap();
+        let birth = BlockMeta {
+            height: born,
+            timestamp: born as i64 * 10,
+        };
+        mori.node_births.insert(&id, &birth).unwrap();
+    }
+    let mut voted = test_node(1, 0);
+    voted.votes.push(Vote {
+        sender: "at1voter".into(),
+        node_id: 1,
+        mov: 19,
+    });
+    mori.mori_nodes.insert(&1, &voted).unwrap();
+    mori.mori_nodes.insert(&4, &test_node(4, 0)).unwrap();
+    mori.set_cur_height(100).unwrap();
+
+    let policy = TurnTimerPolicy {
+        deadline_blocks: Some(50),
+        deadline_secs: None,
+        fallback: TimeoutFallback::Pass,
+        interval_secs: 30,
+    };
+    let nodes = mori.get_all_nodes().unwrap();
+    assert!(mori.turn_deadlines(&nodes).unwrap().is_empty());
+    mori.turn_timers.lock().unwrap().policy = Some(policy);
+    let deadlines = mori.turn_deadlines(&nodes).unwrap();
+    assert_eq!(
+        deadlines
+            .iter()
+            .map(|d| (d.node_id, d.height, d.expired))
+            .collect::<Vec<_>>(),
+        vec![(2, Some(70), true), (3, Some(145), false)]
+    );
+
+    // the voters can still move on 2, so they forfeit the line through 1
+    assert_eq!(mori.check_turns(&policy, 0).unwrap(), vec![2]);
+    assert!(!mori.mori_nodes.contain(&2).unwrap());
+    assert!(!mori.mori_nodes.contain(&1).unwrap());
+    let game = mori.finished_games.get(&2).unwrap().unwrap();
+    assert_eq!((game.root_id, game.outcome), (1, GameOutcome::WhiteWins));
+    assert!(mori.check_turns(&policy, 0).unwrap().is_empty());
+
+    // prune leaves 3 to the timers
+    let prune = PruneOptions {
+        leaf_ttl_blocks: Some(1),
+        ..Default::default()
+    };
+    assert!(mori.prune(prune).unwrap().pruned_leaves.is_empty());
+
+    // by wall clock, 3 was born at 950
+    let by_time = TurnTimerPolicy {
+        deadline_blocks: None,
+        deadline_secs: Some(60),
+        ..policy
+    };
+    assert!(mori.check_turns(&by_time, 1000).unwrap().is_empty());
+    assert_eq!(mori.check_turns(&by_time, 1010).unwrap(), vec![3]);
+
+    // the AI is unreachable, the move of 4 is retried only after a while,
+    // also after a restart
+    let best = TurnTimerPolicy {
+        fallback: TimeoutFallback::BestSquare,
+        ..by_time
+    };
+    let born = BlockMeta {
+        height: 100,
+        timestamp: 1000,
+    };
+    mori.node_births.insert(&4, &born).unwrap();
+    let timed_out = || mori.timed_out_turns.get(&4).unwrap();
+    assert!(mori.check_turns(&best, 1060).unwrap().is_empty());
+    assert_eq!(timed_out(), Some(1060));
+    *mori.turn_timers.lock().unwrap() = TurnTimers { policy: Some(best) };
+    mori.check_turns(&best, 1100).unwrap();
+    assert_eq!(timed_out(), Some(1060));
+    mori.check_turns(&best, 1060 + FALLBACK_RETRY_SECS).unwrap();
+    assert_eq!(timed_out(), Some(1060 + FALLBACK_RETRY_SECS));
+
+    // a corner beats the square next to it
+    let mut node = test_node(5, 0);
+    node.valid_movs = vec![9, 19, 0, PASS];
+    assert_eq!(fallback_move(TimeoutFallback::BestSquare, &node), Some(0));
+    assert_eq!(fallback_move(TimeoutFallback::Pass, &node), None);
+    assert!(fallback_move(TimeoutFallback::Random, &node).is_some_and(|m| m != PASS));
+    node.valid_movs = vec![PASS];
+    for fallback in [
+        TimeoutFallback::Random,
+        TimeoutFallback::BestSquare,
+        TimeoutFallback::Pass,
+    ] {
+        assert_eq!(fallback_move(fallback, &node), Some(PASS));
+    }
+
+    assert_eq!(
+        TimeoutFallback::from_str("pass").unwrap(),
+        TimeoutFallback::Pass
+    );
+    assert!(TimeoutFallback::from_str("ai").is_err());
+}
@@ -1,80 +1,243 @@
-use std::{ops::Deref, str::FromStr};
+use std::{fmt, ops::Deref, str::FromStr};
 
-use aleo_rust::{Address, Entry, Field, Identifier, Literal, Network, Plaintext};
-use anyhow::anyhow;
+use crate::error::{MoriError, Result};
+use aleo_rust::{Address, Entry, Field, Identifier, Literal, Network, Plaintext, Record};
+use serde::{Deserialize, Serialize};
 
-use crate::cores::NodeEdge;
+/// Decodes a value the program hands us as a plaintext.
+pub trait FromAleoPlaintext<N: Network>: Sized {
+    fn from_plaintext(plaintext: &Plaintext<N>) -> Result<Self>;
+}
+
+/// Decodes a program struct from the data of a decrypted record.
+pub trait FromAleoRecord<N: Network>: Sized {
+    fn from_record(record: &Record<N, Plaintext<N>>) -> Result<Self>;
+}
+
+/// Encodes a value as an Aleo input string, e.g. `1u128` or `{ node_id: 1u128, mov: 0u8 }`.
+pub trait ToAleoInput {
+    fn to_input(&self) -> String;
+}
+
+pub fn entry_to_plain<N: Network>(e: &Entry<N, Plaintext<N>>) -> Result<&Plaintext<N>> {
+    match e {
+        Entry::Private(v) | Entry::Public(v) | Entry::Constant(v) => Ok(v),
+    }
+}
+
+pub fn struct_member<'a, N: Network>(
+    plaintext: &'a Plaintext<N>,
+    name: &str,
+) -> Result<&'a Plaintext<N>> {
+    let Plaintext::Struct(members, _) = plaintext else {
+        return Err(MoriError::decode(format!(
+            "expected a struct with field {name}"
+        )));
+    };
+    let ident = Identifier::from_str(name).map_err(MoriError::decode)?;
+    members
+        .get(&ident)
+        .ok_or(MoriError::decode(format!("missing field {name}")))
+}
+
+pub fn record_member<'a, N: Network>(
+    record: &'a Record<N, Plaintext<N>>,
+    name: &str,
+) -> Result<&'a Plaintext<N>> {
+    let ident = Identifier::from_str(name).map_err(MoriError::decode)?;
+    let entry = record
+        .data()
+        .get(&ident)
+        .ok_or(MoriError::decode(format!("missing record field {name}")))?;
+    entry_to_plain(entry)
+}
+
+macro_rules! impl_aleo_literal {
+    ($($ty:ty => $variant:ident, $suffix:literal;)+) => {
+        $(
+            impl<N: Network> FromAleoPlaintext<N> for $ty {
+                fn from_plaintext(plaintext: &Plaintext<N>) -> Result<Self> {
+                    match plaintext {
+                        Plaintext::Literal(Literal::$variant(v), _) => Ok(**v),
+                        _ => Err(MoriError::decode(concat!("invalid ", $suffix, " plaintext"))),
+                    }
+                }
+            }
+
+            impl ToAleoInput for $ty {
+                fn to_input(&self) -> String {
+                    format!(concat!("{}", $suffix), self)
+                }
+            }
+        )+
+    };
+}
+
+impl_aleo_literal! {
+    u8 => U8, "u8";
+    u16 => U16, "u16";
+    u32 => U32, "u32";
+    u64 => U64, "u64";
+    u128 => U128, "u128";
+    i8 => I8, "i8";
+    i16 => I16, "i16";
+    i32 => I32, "i32";
+    i64 => I64, "i64";
+    i128 => I128, "i128";
+}
+
+impl<N: Network> FromAleoPlaintext<N> for bool {
+    fn from_plaintext(plaintext: &Plaintext<N>) -> Result<Self> {
+        match plaintext {
+            Plaintext::Literal(Literal::Boolean(v), _) => Ok(**v),
+            _ => Err(MoriError::decode("invalid boolean plaintext")),
+        }
+    }
+}
 
-pub fn entry_to_plain<N: Network>(e: &Entry<N, Plaintext<N>>) -> anyhow::Result<&Plaintext<N>> {
-    if let Entry::Private(v) = e {
-        Ok(v)
-    } else {
-        anyhow::bail!("invalid entry")
+impl ToAleoInput for bool {
+    fn to_input(&self) -> String {
+        self.to_string()
     }
 }
 
-pub fn handle_u8_plaintext<N: Network>(plaintext: &Plaintext<N>) -> anyhow::Result<u8> {
-    if let Plaintext::Literal(Literal::U8(v), _) = plaintext {
-        Ok(*v.deref())
-    } else {
-        anyhow::bail!("invalid u32 plaintext")
+impl<N: Network> FromAleoPlaintext<N> for Address<N> {
+    fn from_plaintext(plaintext: &Plaintext<N>) -> Result<Self> {
+        match plaintext {
+            Plaintext::Literal(Literal::Address(v), _) => Ok(*v),
+            _ => Err(MoriError::decode("invalid address plaintext")),
+        }
     }
 }
 
-pub fn handle_i8_plaintext<N: Network>(plaintext: &Plaintext<N>) -> anyhow::Result<i8> {
-    if let Plaintext::Literal(Literal::I8(v), _) = plaintext {
-        Ok(*v.deref())
-    } else {
-        anyhow::bail!("invalid u32 plaintext")
+impl<N: Network> ToAleoInput for Address<N> {
+    fn to_input(&self) -> String {
+        self.to_string()
     }
 }
 
-pub fn handle_u128_plaintext<N: Network>(plaintext: &Plaintext<N>) -> anyhow::Result<u128> {
-    if let Plaintext::Literal(Literal::U128(v), _) = plaintext {
-        Ok(*v.deref())
-    } else {
-        anyhow::bail!("invalid u128 plaintext")
+impl<N: Network> FromAleoPlaintext<N> for Field<N> {
+    fn from_plaintext(plaintext: &Plaintext<N>) -> Result<Self> {
+        match plaintext {
+            Plaintext::Literal(Literal::Field(v), _) => Ok(*v),
+            _ => Err(MoriError::decode("invalid field plaintext")),
+        }
     }
 }
 
-pub fn handle_field_plaintext<N: Network>(plaintext: &Plaintext<N>) -> anyhow::Result<Field<N>> {
-    if let Plaintext::Literal(Literal::Field(v), _) = plaintext {
-        Ok(*v)
-    } else {
-        anyhow::bail!("invalid field plaintext")
+impl<N: Network> ToAleoInput for Field<N> {
+    fn to_input(&self) -> String {
+        self.to_string()
     }
 }
 
-pub fn handle_addr_plaintext<N: Network>(plaintext: &Plaintext<N>) -> anyhow::Result<Address<N>> {
-    if let Plaintext::Literal(Literal::Address(v), _) = plaintext {
-        Ok(*v)
-    } else {
-        anyhow::bail!("invalid address plaintext")
+/// An address in its `aleo1...` form, so the types holding one do not depend
+/// on the network.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AleoAddress(pub String);
+
+impl Deref for AleoAddress {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for AleoAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for AleoAddress {
+    fn from(address: &str) -> Self {
+        Self(address.to_string())
+    }
+}
+
+impl<N: Network> FromAleoPlaintext<N> for AleoAddress {
+    fn from_plaintext(plaintext: &Plaintext<N>) -> Result<Self> {
+        Address::<N>::from_plaintext(plaintext).map(|a| Self(a.to_string()))
     }
 }
 
-pub fn handle_from_plaintext<N: Network>(plaintext: &Plaintext<N>) -> anyhow::Result<NodeEdge> {
-    if let Plaintext::Struct(s,_) = plaintext {
-        let (from_node_id_ident, from_mov_ident) = (
-            Identifier::from_str("node_id")?,
-            Identifier::from_str("mov")?,
-        );
-
-        let (from_node_id_entry, from_mov_entry) = (
-            s.get(&from_node_id_ident).ok_or(anyhow!("Invalid record"))?,
-            s.get(&from_mov_ident).ok_or(anyhow!("Invalid record"))?,
-        );
-
-        let (from_node_id, from_mov) = (
-            handle_u128_plaintext(from_node_id_entry)?,
-            handle_u8_plaintext(from_mov_entry)?,
-        );
-
-        Ok(NodeEdge {
-            node_id: from_node_id,
-            mov: from_mov,
-        })
-    } else {
-        anyhow::bail!("invalid address plaintext")
+impl ToAleoInput for AleoAddress {
+    fn to_input(&self) -> String {
+        self.0.clone()
     }
 }
+
+/// Implements [`FromAleoPlaintext`], [`FromAleoRecord`] and [`ToAleoInput`] for a
+/// struct mirroring a program struct. Fields listed under `off_chain` are not
+/// part of the program struct and start from their default.
+///
+/// ```ignore
+/// aleo_struct!(NodeEdge { node_id, mov });
+/// aleo_struct!(GameNode { node_id, state, from, game_status } off_chain { valid_movs, votes });
+/// ```
+macro_rules! aleo_struct {
+    ($name:ident { $($field:ident),+ $(,)? } $(off_chain { $($extra:ident),+ $(,)? })?) => {
+        impl<N: aleo_rust::Network> $crate::utils::FromAleoPlaintext<N> for $name {
+            fn from_plaintext(plaintext: &aleo_rust::Plaintext<N>) -> $crate::error::Result<Self> {
+                Ok(Self {
+                    $($field: $crate::utils::FromAleoPlaintext::from_plaintext(
+                        $crate::utils::struct_member(plaintext, stringify!($field))?,
+                    )?,)+
+                    $($($extra: Default::default(),)+)?
+                })
+            }
+        }
+
+        impl<N: aleo_rust::Network> $crate::utils::FromAleoRecord<N> for $name {
+            fn from_record(
+                record: &aleo_rust::Record<N, aleo_rust::Plaintext<N>>,
+            ) -> $crate::error::Result<Self> {
+                Ok(Self {
+                    $($field: $crate::utils::FromAleoPlaintext::from_plaintext(
+                        $crate::utils::record_member(record, stringify!($field))?,
+                    )?,)+
+                    $($($extra: Default::default(),)+)?
+                })
+            }
+        }
+
+        impl $crate::utils::ToAleoInput for $name {
+            fn to_input(&self) -> String {
+                let members = [$(
+                    format!(
+                        "{}: {}",
+                        stringify!($field),
+                        $crate::utils::ToAleoInput::to_input(&self.$field)
+                    ),
+                )+];
+                format!("{{ {} }}", members.join(", "))
+            }
+        }
+    };
+}
+
+pub(crate) use aleo_struct;
+
+#[test]
+fn test_aleo_struct_roundtrip() {
+    use crate::cores::NodeEdge;
+    use aleo_rust::Testnet3;
+
+    let edge = NodeEdge {
+        node_id: 42,
+        mov: 29,
+    };
+    let input = edge.to_input();
+    assert_eq!(input, "{ node_id: 42u128, mov: 29u8 }");
+
+    let plaintext = Plaintext::<Testnet3>::from_str(&input).unwrap();
+    assert_eq!(NodeEdge::from_plaintext(&plaintext).unwrap(), edge);
+    assert_eq!(
+        u128::from_plaintext(struct_member(&plaintext, "node_id").unwrap()).unwrap(),
+        42
+    );
+    assert!(u8::from_plaintext(struct_member(&plaintext, "node_id").unwrap()).is_err());
+    assert!(struct_member(&plaintext, "state").is_err());
+}